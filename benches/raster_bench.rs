@@ -3,29 +3,14 @@ use std::time::{Duration, Instant};
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use fuwa::*;
 use glam::*;
-use winit::dpi::LogicalSize;
-use winit::event_loop::EventLoop;
-use winit::window::{Window, WindowBuilder};
 
 const WIDTH: u32 = 1280;
 const HEIGHT: u32 = 720;
 
-const SIZE: f32 = 1.0;
-
 fn criterion_benchmark(c: &mut Criterion) {
-    let window = {
-        let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
-        WindowBuilder::new()
-            .with_title("FUWA BENCHMARKS TEST")
-            .with_inner_size(size)
-            .with_min_inner_size(size)
-            .build(&EventLoop::new())
-            .unwrap()
-    };
+    let mut fuwa = Fuwa::new_offscreen(WIDTH, HEIGHT, num_cpus::get());
 
-    let mut fuwa = Fuwa::new(WIDTH, HEIGHT, num_cpus::get(), false, None, &window);
-
-    let frag_shader = ColorBlend::new();
+    let frag_shader = fuwa.add_fragment_shader(ColorBlend::new());
     let mut vert_shader = BasicVertexShader::new();
 
     let colored_cube = colored_cube(1.);
@@ -33,9 +18,6 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     let translation = vec3a(0., 0., 2.0);
 
-    let black = fuwa::colors::BLACK;
-    let white = fuwa::colors::WHITE;
-
     let rotation = Mat3::from_cols(
         vec3(0.69670665, -0.40504977, -0.59205955),
         vec3(0.0, 0.8253356, -0.5646425),
@@ -62,12 +44,12 @@ fn criterion_benchmark(c: &mut Criterion) {
                 pipeline::draw(
                     black_box(&mut fuwa),
                     black_box(&vert_shader),
-                    black_box(0),
+                    black_box(frag_shader),
                     black_box(&active_model),
                 );
 
-                fuwa.render(black_box(&frag_shader), black_box(0));
-                fuwa.present();
+                fuwa.render(black_box(frag_shader));
+                fuwa.present().unwrap();
 
                 total += end;
             }
@@ -85,13 +67,13 @@ fn criterion_benchmark(c: &mut Criterion) {
                 pipeline::draw(
                     black_box(&mut fuwa),
                     black_box(&vert_shader),
-                    black_box(0),
+                    black_box(frag_shader),
                     black_box(&active_model),
                 );
                 let end = start.elapsed();
 
-                fuwa.render(black_box(&frag_shader), black_box(0));
-                fuwa.present();
+                fuwa.render(black_box(frag_shader));
+                fuwa.present().unwrap();
 
                 total += end;
             }
@@ -109,13 +91,13 @@ fn criterion_benchmark(c: &mut Criterion) {
                 pipeline::draw(
                     black_box(&mut fuwa),
                     black_box(&vert_shader),
-                    black_box(0),
+                    black_box(frag_shader),
                     black_box(&active_model),
                 );
                 let end = start.elapsed();
 
-                fuwa.render(black_box(&frag_shader), black_box(0));
-                fuwa.present();
+                fuwa.render(black_box(frag_shader));
+                fuwa.present().unwrap();
 
                 total += end;
             }
@@ -132,14 +114,14 @@ fn criterion_benchmark(c: &mut Criterion) {
                 pipeline::draw(
                     black_box(&mut fuwa),
                     black_box(&vert_shader),
-                    black_box(0),
+                    black_box(frag_shader),
                     black_box(&active_model),
                 );
 
-                fuwa.render(black_box(&frag_shader), black_box(0));
+                fuwa.render(black_box(frag_shader));
 
                 let start = Instant::now();
-                fuwa.present();
+                fuwa.present().unwrap();
                 let end = start.elapsed();
 
                 total += end;
@@ -155,12 +137,12 @@ fn criterion_benchmark(c: &mut Criterion) {
             pipeline::draw(
                 black_box(&mut fuwa),
                 black_box(&vert_shader),
-                black_box(0),
+                black_box(frag_shader),
                 black_box(&active_model),
             );
 
-            fuwa.render(black_box(&frag_shader), black_box(0));
-            fuwa.present();
+            fuwa.render(black_box(frag_shader));
+            fuwa.present().unwrap();
         });
     });
 }
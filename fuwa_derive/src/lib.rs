@@ -0,0 +1,328 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Field, Fields, Ident, Index, Meta, NestedMeta};
+
+//Operator, its method, and whether it's also needed against an f32
+const OPERATORS: [(&str, &str, bool); 4] = [
+    ("Add", "add", false),
+    ("Sub", "sub", false),
+    ("Mul", "mul", true),
+    ("Div", "div", true),
+];
+
+#[derive(Copy, Clone, PartialEq)]
+enum Interpolation {
+    Perspective,
+    NoPerspective,
+    Flat,
+}
+
+/// Implements `FSInput` for a struct whose fields are all `FSInput` themselves,
+/// like `f32` and glam vectors, by generating the arithmetic field by field.
+/// The struct still needs to derive `Clone` and `Copy`.
+///
+/// Fields can be marked with `#[interpolate(perspective)]`, the default,
+/// `#[interpolate(noperspective)]` or `#[interpolate(flat)]`.
+#[proc_macro_derive(FSInput, attributes(interpolate))]
+pub fn derive_fs_input(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+/// Implements `FSInputSimd` for a struct whose fields all implement it, by
+/// generating a `<Name>Lanes` struct with the same fields in their lane layout.
+/// The struct needs to implement `FSInput` as well.
+#[proc_macro_derive(FSInputSimd)]
+pub fn derive_fs_input_simd(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand_simd(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+/// Implements `FragmentOutput` for a struct of `Vec4`s, writing each field to
+/// the color attachment at the same index, in declaration order.
+/// The struct still needs to derive `Clone` and `Copy`.
+#[proc_macro_derive(FragmentOutput)]
+pub fn derive_fragment_output(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand_output(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "FSInput can only be derived for structs",
+            ))
+        }
+    };
+
+    if fields.is_empty() {
+        return Err(syn::Error::new_spanned(
+            input,
+            "FSInput can't be derived for structs without fields",
+        ));
+    }
+
+    //Named fields by name, tuple fields by index
+    let members = fields
+        .iter()
+        .enumerate()
+        .map(|(index, field)| match &field.ident {
+            Some(name) => quote!(#name),
+            None => {
+                let index = Index::from(index);
+                quote!(#index)
+            }
+        })
+        .collect::<Vec<_>>();
+    let modes = fields
+        .iter()
+        .map(interpolation)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    //Nested FSInputs pick their own modes unless the whole field isn't perspective correct
+    let scaled = members.iter().zip(&modes).map(|(member, mode)| match mode {
+        Interpolation::Perspective => {
+            quote!(#member: ::fuwa::FSInput::scale_by_mode(self.#member, perspective, linear))
+        }
+        _ => quote!(#member: ::fuwa::FSInput::scale_by_mode(self.#member, linear, linear)),
+    });
+    let copied = members.iter().zip(&modes).map(|(member, mode)| match mode {
+        Interpolation::Flat => quote!(self.#member = provoking.#member;),
+        _ => quote!(::fuwa::FSInput::copy_flat(&mut self.#member, &provoking.#member);),
+    });
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let mut impls = TokenStream2::new();
+
+    for (operator, method, scalar) in OPERATORS.iter() {
+        let operator_assign = Ident::new(&format!("{}Assign", operator), Span::call_site());
+        let method_assign = Ident::new(&format!("{}_assign", method), Span::call_site());
+        let operator = Ident::new(operator, Span::call_site());
+        let method = Ident::new(method, Span::call_site());
+
+        //Field by field against another struct
+        impls.extend(quote! {
+            impl #impl_generics ::core::ops::#operator for #name #type_generics #where_clause {
+                type Output = Self;
+
+                #[inline]
+                fn #method(self, rhs: Self) -> Self {
+                    Self {
+                        #(#members: ::core::ops::#operator::#method(self.#members, rhs.#members),)*
+                    }
+                }
+            }
+
+            impl #impl_generics ::core::ops::#operator_assign for #name #type_generics #where_clause {
+                #[inline]
+                fn #method_assign(&mut self, rhs: Self) {
+                    #(::core::ops::#operator_assign::#method_assign(&mut self.#members, rhs.#members);)*
+                }
+            }
+        });
+
+        //Every field against the same scalar
+        if *scalar {
+            impls.extend(quote! {
+                impl #impl_generics ::core::ops::#operator<f32> for #name #type_generics #where_clause {
+                    type Output = Self;
+
+                    #[inline]
+                    fn #method(self, rhs: f32) -> Self {
+                        Self {
+                            #(#members: ::core::ops::#operator::#method(self.#members, rhs),)*
+                        }
+                    }
+                }
+
+                impl #impl_generics ::core::ops::#operator_assign<f32> for #name #type_generics #where_clause {
+                    #[inline]
+                    fn #method_assign(&mut self, rhs: f32) {
+                        #(::core::ops::#operator_assign::#method_assign(&mut self.#members, rhs);)*
+                    }
+                }
+            });
+        }
+    }
+
+    Ok(quote! {
+        #impls
+
+        impl #impl_generics ::fuwa::FSInput for #name #type_generics #where_clause {
+            #[inline]
+            fn scale_by_mode(self, perspective: f32, linear: f32) -> Self {
+                Self {
+                    #(#scaled,)*
+                }
+            }
+
+            #[inline]
+            fn copy_flat(&mut self, provoking: &Self) {
+                #(#copied)*
+            }
+        }
+    })
+}
+
+fn expand_simd(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) if !data.fields.is_empty() => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "FSInputSimd can only be derived for structs with fields",
+            ))
+        }
+    };
+
+    //The lanes struct would need the same generics, with every parameter bound by FSInputSimd
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "FSInputSimd can't be derived for generic structs",
+        ));
+    }
+
+    let name = &input.ident;
+    let vis = &input.vis;
+    let lanes_name = Ident::new(&format!("{}Lanes", name), name.span());
+    let lane_fields = fields.iter().map(|field| {
+        let vis = &field.vis;
+        let ty = &field.ty;
+        match &field.ident {
+            Some(name) => quote!(#vis #name: <#ty as ::fuwa::FSInputSimd>::Lanes),
+            None => quote!(#vis <#ty as ::fuwa::FSInputSimd>::Lanes),
+        }
+    });
+    let lanes_struct = match fields {
+        Fields::Named(_) => quote!(#vis struct #lanes_name { #(#lane_fields,)* }),
+        _ => quote!(#vis struct #lanes_name(#(#lane_fields,)*);),
+    };
+
+    let conversions = fields.iter().enumerate().map(|(index, field)| {
+        let ty = &field.ty;
+        let member = match &field.ident {
+            Some(name) => quote!(#name),
+            None => {
+                let index = Index::from(index);
+                quote!(#index)
+            }
+        };
+        let lanes = (0..8usize).map(|lane| quote!(values[#lane].#member));
+        quote!(#member: <#ty as ::fuwa::FSInputSimd>::to_lanes(&[#(#lanes,)*]))
+    });
+
+    let doc = format!("`{}` laid out as one lane per fragment.", name);
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Copy, Clone)]
+        #lanes_struct
+
+        impl ::fuwa::FSInputSimd for #name {
+            type Lanes = #lanes_name;
+
+            #[inline]
+            fn to_lanes(values: &[Self; 8]) -> Self::Lanes {
+                #lanes_name {
+                    #(#conversions,)*
+                }
+            }
+        }
+    })
+}
+
+fn expand_output(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "FragmentOutput can only be derived for structs",
+            ))
+        }
+    };
+
+    let count = fields.len();
+    let arms = fields.iter().enumerate().map(|(index, field)| {
+        let member = match &field.ident {
+            Some(name) => quote!(#name),
+            None => {
+                let member = Index::from(index);
+                quote!(#member)
+            }
+        };
+        quote!(#index => self.#member)
+    });
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::fuwa::FragmentOutput for #name #type_generics #where_clause {
+            const COUNT: usize = #count;
+
+            #[inline]
+            fn color(&self, index: usize) -> ::fuwa::glam::Vec4 {
+                match index {
+                    #(#arms,)*
+                    _ => panic!("Color index out of bounds!"),
+                }
+            }
+        }
+    })
+}
+
+//Reads the field's #[interpolate(...)] attribute, if it has one
+fn interpolation(field: &Field) -> syn::Result<Interpolation> {
+    let mut mode = Interpolation::Perspective;
+
+    for attribute in field
+        .attrs
+        .iter()
+        .filter(|attribute| attribute.path.is_ident("interpolate"))
+    {
+        let list = match attribute.parse_meta()? {
+            Meta::List(list) if list.nested.len() == 1 => list,
+            meta => {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    "expected #[interpolate(perspective)], #[interpolate(noperspective)] or #[interpolate(flat)]",
+                ))
+            }
+        };
+
+        mode = match &list.nested[0] {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("perspective") => {
+                Interpolation::Perspective
+            }
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("noperspective") => {
+                Interpolation::NoPerspective
+            }
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("flat") => Interpolation::Flat,
+            nested => {
+                return Err(syn::Error::new_spanned(
+                    nested,
+                    "unknown interpolation, expected perspective, noperspective or flat",
+                ))
+            }
+        };
+    }
+
+    Ok(mode)
+}
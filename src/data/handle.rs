@@ -0,0 +1,197 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
+
+/// Refers to a resource owned by Fuwa. Each slot counts how many times it was
+/// reused, so a handle to something that got removed never finds whatever took its place.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: u32, generation: u32) -> Self {
+        Self {
+            index,
+            generation,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    //Same slot, for maps holding type erased resources
+    pub(crate) fn cast<U>(self) -> Handle<U> {
+        Handle::new(self.index, self.generation)
+    }
+}
+
+//Implemented by hand, so T doesn't need to implement any of them
+impl<T> Copy for Handle<T> {}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+struct Entry<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+//Generational arena, where removed slots get reused with a new generation
+pub(crate) struct HandleMap<T> {
+    entries: Vec<Entry<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> HandleMap<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, value: T) -> Handle<T> {
+        match self.free.pop() {
+            Some(index) => {
+                let entry = &mut self.entries[index as usize];
+                entry.value = Some(value);
+                Handle::new(index, entry.generation)
+            }
+            None => {
+                self.entries.push(Entry {
+                    generation: 0,
+                    value: Some(value),
+                });
+                Handle::new(self.entries.len() as u32 - 1, 0)
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.entries
+            .get(handle.index as usize)
+            .filter(|entry| entry.generation == handle.generation)
+            .and_then(|entry| entry.value.as_ref())
+    }
+
+    pub(crate) fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.entries
+            .get_mut(handle.index as usize)
+            .filter(|entry| entry.generation == handle.generation)
+            .and_then(|entry| entry.value.as_mut())
+    }
+
+    pub(crate) fn contains(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+
+    pub(crate) fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        let entry = self
+            .entries
+            .get_mut(handle.index as usize)
+            .filter(|entry| entry.generation == handle.generation)?;
+        let value = entry.value.take()?;
+        entry.generation = entry.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        Some(value)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                entry
+                    .value
+                    .as_ref()
+                    .map(|value| (Handle::new(index as u32, entry.generation), value))
+            })
+    }
+
+    pub(crate) fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.entries
+            .iter_mut()
+            .filter_map(|entry| entry.value.as_mut())
+    }
+}
+
+//For handles which were already checked
+impl<T> Index<Handle<T>> for HandleMap<T> {
+    type Output = T;
+
+    fn index(&self, handle: Handle<T>) -> &T {
+        self.get(handle).expect("Invalid handle!")
+    }
+}
+
+impl<T> IndexMut<Handle<T>> for HandleMap<T> {
+    fn index_mut(&mut self, handle: Handle<T>) -> &mut T {
+        self.get_mut(handle).expect("Invalid handle!")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_slots_are_reused_with_a_new_generation() {
+        let mut map = HandleMap::new();
+        let first = map.insert("first");
+        let second = map.insert("second");
+        assert_eq!(map.remove(first), Some("first"));
+
+        let third = map.insert("third");
+        assert_eq!(third.index(), first.index());
+        assert_ne!(third, first);
+        assert_eq!(map.get(first), None);
+        assert_eq!(map.get(third), Some(&"third"));
+        assert_eq!(map.get(second), Some(&"second"));
+    }
+
+    #[test]
+    fn stale_handles_can_not_remove_or_change_the_new_value() {
+        let mut map = HandleMap::new();
+        let stale = map.insert(1);
+        map.remove(stale);
+        let current = map.insert(2);
+
+        assert_eq!(map.remove(stale), None);
+        assert!(map.get_mut(stale).is_none());
+        assert!(!map.contains(stale));
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(current, &2)]);
+    }
+}
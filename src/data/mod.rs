@@ -1,2 +1,8 @@
+mod handle;
+pub use handle::*;
+
 mod uniforms;
 pub use uniforms::*;
+
+mod resources;
+pub(crate) use resources::*;
@@ -0,0 +1,110 @@
+use crate::{FSInput, FragmentShader, Fuwa, Handle, IndexedVertexBuffer, RenderTarget};
+use std::any::Any;
+
+//Shaders and vertex buffers of every type share one map each, so their
+//handles are unique no matter the type they point to
+pub(crate) type AnyResource = Box<dyn Any + Send + Sync>;
+
+impl<T: RenderTarget> Fuwa<T> {
+    /// Hands the shader over to Fuwa. Its handle picks it when drawing and rendering.
+    pub fn add_fragment_shader<F: FSInput, S: FragmentShader<F> + 'static>(
+        &mut self,
+        shader: S,
+    ) -> Handle<S> {
+        self.shaders.insert(Box::new(shader)).cast()
+    }
+
+    pub fn get_fragment_shader<S: 'static>(&self, handle: Handle<S>) -> Option<&S> {
+        self.shaders.get(handle.cast())?.downcast_ref()
+    }
+
+    pub fn get_fragment_shader_mut<S: 'static>(&mut self, handle: Handle<S>) -> Option<&mut S> {
+        self.shaders.get_mut(handle.cast())?.downcast_mut()
+    }
+
+    /// Fragments which were drawn with the shader but not rendered yet get dropped.
+    pub fn remove_fragment_shader<S: 'static>(&mut self, handle: Handle<S>) -> Option<S> {
+        //Only removes it if it's also of the right type
+        self.get_fragment_shader(handle)?;
+        self.release_shader_fragments(handle.cast());
+        self.reset_draw_states();
+        self.shaders
+            .remove(handle.cast())
+            .and_then(|shader| shader.downcast().ok())
+            .map(|shader| *shader)
+    }
+
+    /// Hands the vertices and indices over to Fuwa, to be drawn by handle.
+    pub fn add_vertex_buffer<V: Send + Sync + 'static>(
+        &mut self,
+        buffer: IndexedVertexBuffer<V>,
+    ) -> Handle<IndexedVertexBuffer<V>> {
+        self.vertex_buffers.insert(Box::new(buffer)).cast()
+    }
+
+    pub fn get_vertex_buffer<V: Send + Sync + 'static>(
+        &self,
+        handle: Handle<IndexedVertexBuffer<V>>,
+    ) -> Option<&IndexedVertexBuffer<V>> {
+        self.vertex_buffers.get(handle.cast())?.downcast_ref()
+    }
+
+    pub fn get_vertex_buffer_mut<V: Send + Sync + 'static>(
+        &mut self,
+        handle: Handle<IndexedVertexBuffer<V>>,
+    ) -> Option<&mut IndexedVertexBuffer<V>> {
+        self.vertex_buffers.get_mut(handle.cast())?.downcast_mut()
+    }
+
+    pub fn remove_vertex_buffer<V: Send + Sync + 'static>(
+        &mut self,
+        handle: Handle<IndexedVertexBuffer<V>>,
+    ) -> Option<IndexedVertexBuffer<V>> {
+        self.get_vertex_buffer(handle)?;
+        self.vertex_buffers
+            .remove(handle.cast())
+            .and_then(|buffer| buffer.downcast().ok())
+            .map(|buffer| *buffer)
+    }
+
+    /// Adds a block of constants of any type, which shaders can read once it's bound.
+    pub fn add_uniform_block<U: Clone + Send + Sync + 'static>(&mut self, block: U) -> Handle<U> {
+        self.uniforms.add_block(block)
+    }
+
+    pub fn get_uniform_block<U: 'static>(&self, handle: Handle<U>) -> Option<&U> {
+        self.uniforms.get_block(handle)
+    }
+
+    /// Changes are seen by the draws after them. Fragments drawn before the change
+    /// keep the old contents, by copying the block if any are waiting to be rendered.
+    pub fn get_uniform_block_mut<U: 'static>(&mut self, handle: Handle<U>) -> Option<&mut U> {
+        self.uniforms.get_block_mut(handle)
+    }
+
+    /// Unbinds the block if it's bound.
+    pub fn remove_uniform_block<U: Send + Sync + 'static>(
+        &mut self,
+        handle: Handle<U>,
+    ) -> Option<U> {
+        self.uniforms.remove_block(handle)
+    }
+
+    /// Makes the block the one of its type which the vertex and fragment shaders
+    /// of every following draw read, replacing the one bound before.
+    pub fn bind_uniform_block<U: 'static>(&mut self, handle: Handle<U>) {
+        assert!(
+            self.uniforms.get_block(handle).is_some(),
+            "Invalid uniform block handle!"
+        );
+        self.uniforms.bind_block(Some(handle));
+    }
+
+    pub fn unbind_uniform_block<U: 'static>(&mut self) {
+        self.uniforms.bind_block::<U>(None);
+    }
+
+    pub fn get_bound_uniform_block<U: 'static>(&self) -> Option<Handle<U>> {
+        self.uniforms.get_bound_block()
+    }
+}
@@ -1,22 +1,331 @@
-use crate::Texture;
-use slab::Slab;
+use crate::{FragmentDerivatives, Handle, HandleMap, Sampler, Texture};
+use glam::*;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
 
-pub struct Uniforms {
-    textures: Slab<Texture>,
+//Everything shaders can read, owned by Fuwa
+pub(crate) struct UniformStorage {
+    textures: HandleMap<Texture>,
+    samplers: HandleMap<Sampler>,
+    blocks: HandleMap<UniformBlock>,
+    //Blocks bound for the next draw
+    bound_blocks: UniformBindings,
+    //Every distinct set of bindings that was drawn with, which fragments refer to by index
+    draw_bindings: Vec<DrawBindings>,
+    draw_binding_ids: HashMap<UniformBindings, u32>,
+    //Attachments of the bound render texture, which can't be sampled
+    bound_textures: Vec<Handle<Texture>>,
 }
 
-impl Uniforms {
+type BlockData = Arc<dyn Any + Send + Sync>;
+
+//The contents are shared with the snapshots of the draws which use the block,
+//and copied when changed while any of them still does
+pub(crate) struct UniformBlock {
+    data: BlockData,
+    clone: fn(&(dyn Any + Send + Sync)) -> BlockData,
+}
+
+impl UniformBlock {
+    fn new<U: Clone + Send + Sync + 'static>(block: U) -> Self {
+        Self {
+            data: Arc::new(block),
+            clone: |data| Arc::new(data.downcast_ref::<U>().unwrap().clone()),
+        }
+    }
+
+    fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.data) > 1
+    }
+
+    fn make_mut(&mut self) -> &mut (dyn Any + Send + Sync) {
+        if self.is_shared() {
+            self.data = (self.clone)(&*self.data);
+        }
+        Arc::get_mut(&mut self.data).unwrap()
+    }
+}
+
+//The bound block of each type, sorted by type
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub(crate) struct UniformBindings {
+    blocks: Vec<(TypeId, Handle<UniformBlock>)>,
+}
+
+//Contents of the blocks bound for a draw, sorted by type
+struct DrawBindings {
+    blocks: Vec<(TypeId, BlockData)>,
+}
+
+impl UniformBindings {
+    fn get(&self, type_id: TypeId) -> Option<Handle<UniformBlock>> {
+        self.blocks
+            .binary_search_by_key(&type_id, |(id, _)| *id)
+            .ok()
+            .map(|index| self.blocks[index].1)
+    }
+
+    fn set(&mut self, type_id: TypeId, handle: Option<Handle<UniformBlock>>) {
+        match (
+            self.blocks.binary_search_by_key(&type_id, |(id, _)| *id),
+            handle,
+        ) {
+            (Ok(index), Some(handle)) => self.blocks[index].1 = handle,
+            (Ok(index), None) => {
+                self.blocks.remove(index);
+            }
+            (Err(index), Some(handle)) => self.blocks.insert(index, (type_id, handle)),
+            (Err(_), None) => (),
+        }
+    }
+}
+
+impl UniformStorage {
     pub(crate) fn new() -> Self {
         Self {
-            textures: Slab::new(),
+            textures: HandleMap::new(),
+            samplers: HandleMap::new(),
+            blocks: HandleMap::new(),
+            bound_blocks: UniformBindings::default(),
+            draw_bindings: Vec::new(),
+            draw_binding_ids: HashMap::new(),
+            bound_textures: Vec::new(),
         }
     }
 
-    pub fn get_texture(&self, handle: usize) -> &Texture {
-        unsafe { self.textures.get_unchecked(handle) }
+    pub(crate) fn get_texture_mut(&mut self, handle: Handle<Texture>) -> Option<&mut Texture> {
+        self.textures.get_mut(handle)
+    }
+
+    pub(crate) fn set_bound_textures(&mut self, textures: Vec<Handle<Texture>>) {
+        self.bound_textures = textures;
     }
 
-    pub fn add_texture(&mut self, texture: Texture) -> usize {
+    pub(crate) fn add_texture(&mut self, texture: Texture) -> Handle<Texture> {
         self.textures.insert(texture)
     }
+
+    pub(crate) fn remove_texture(&mut self, handle: Handle<Texture>) -> Option<Texture> {
+        self.textures.remove(handle)
+    }
+
+    pub(crate) fn add_sampler(&mut self, sampler: Sampler) -> Handle<Sampler> {
+        self.samplers.insert(sampler)
+    }
+
+    pub(crate) fn add_block<U: Clone + Send + Sync + 'static>(&mut self, block: U) -> Handle<U> {
+        self.blocks.insert(UniformBlock::new(block)).cast()
+    }
+
+    pub(crate) fn get_block<U: 'static>(&self, handle: Handle<U>) -> Option<&U> {
+        self.blocks.get(handle.cast())?.data.downcast_ref()
+    }
+
+    pub(crate) fn get_block_mut<U: 'static>(&mut self, handle: Handle<U>) -> Option<&mut U> {
+        self.get_block(handle)?;
+        let block = self.blocks.get_mut(handle.cast())?;
+        //Later draws with the same bindings can't reuse the old contents
+        if block.is_shared() {
+            self.draw_binding_ids.clear();
+        }
+        block.make_mut().downcast_mut()
+    }
+
+    pub(crate) fn remove_block<U: Send + Sync + 'static>(
+        &mut self,
+        handle: Handle<U>,
+    ) -> Option<U> {
+        self.get_block(handle)?;
+        if self.bound_blocks.get(TypeId::of::<U>()) == Some(handle.cast()) {
+            self.bound_blocks.set(TypeId::of::<U>(), None);
+        }
+        let mut block = self.blocks.remove(handle.cast())?;
+        block.make_mut();
+        let data = block.data.downcast::<U>().ok()?;
+        Arc::try_unwrap(data).ok()
+    }
+
+    pub(crate) fn bind_block<U: 'static>(&mut self, handle: Option<Handle<U>>) {
+        self.bound_blocks
+            .set(TypeId::of::<U>(), handle.map(|handle| handle.cast()));
+    }
+
+    pub(crate) fn get_bound_block<U: 'static>(&self) -> Option<Handle<U>> {
+        self.bound_blocks
+            .get(TypeId::of::<U>())
+            .map(|handle| handle.cast())
+    }
+
+    //Remembers the current bindings for the fragments of a draw
+    pub(crate) fn snapshot_bindings(&mut self) -> u32 {
+        if let Some(id) = self.draw_binding_ids.get(&self.bound_blocks) {
+            return *id;
+        }
+
+        let id = self.draw_bindings.len() as u32;
+        let blocks = &self.blocks;
+        let contents = self
+            .bound_blocks
+            .blocks
+            .iter()
+            .map(|&(type_id, handle)| (type_id, blocks[handle].data.clone()))
+            .collect();
+        self.draw_bindings.push(DrawBindings { blocks: contents });
+        self.draw_binding_ids.insert(self.bound_blocks.clone(), id);
+        id
+    }
+
+    //Only once no fragments refer to the snapshots anymore
+    pub(crate) fn clear_draw_bindings(&mut self) {
+        self.draw_bindings.clear();
+        self.draw_binding_ids.clear();
+    }
+
+    //What the vertex shaders of the next draw see
+    pub(crate) fn bound(&self) -> Uniforms<'_> {
+        Uniforms {
+            storage: self,
+            bindings: Bindings::Bound(&self.bound_blocks),
+        }
+    }
+
+    //What the fragments of an earlier draw see
+    pub(crate) fn for_draw(&self, bindings: u32) -> Uniforms<'_> {
+        Uniforms {
+            storage: self,
+            bindings: Bindings::Draw(&self.draw_bindings[bindings as usize]),
+        }
+    }
+}
+
+/// Everything shaders can read: textures and samplers by handle, and one bound
+/// uniform block of each type. Blocks are the ones that were bound when the
+/// triangle was drawn, so each draw can use different constants with the same shaders.
+#[derive(Copy, Clone)]
+pub struct Uniforms<'a> {
+    storage: &'a UniformStorage,
+    bindings: Bindings<'a>,
+}
+
+#[derive(Copy, Clone)]
+enum Bindings<'a> {
+    Bound(&'a UniformBindings),
+    Draw(&'a DrawBindings),
+}
+
+impl<'a> Uniforms<'a> {
+    /// None if the texture was removed, or is an attachment of the bound render texture.
+    pub fn get_texture(&self, handle: Handle<Texture>) -> Option<&'a Texture> {
+        if self.storage.bound_textures.contains(&handle) {
+            return None;
+        }
+        self.storage.textures.get(handle)
+    }
+
+    pub fn get_sampler(&self, handle: Handle<Sampler>) -> Option<&'a Sampler> {
+        self.storage.samplers.get(handle)
+    }
+
+    /// The bound block of the given type, if there is one.
+    pub fn get_block<U: 'static>(&self) -> Option<&'a U> {
+        let type_id = TypeId::of::<U>();
+        match self.bindings {
+            Bindings::Bound(bindings) => self.storage.blocks[bindings.get(type_id)?]
+                .data
+                .downcast_ref(),
+            Bindings::Draw(bindings) => {
+                let index = bindings
+                    .blocks
+                    .binary_search_by_key(&type_id, |(id, _)| *id)
+                    .ok()?;
+                bindings.blocks[index].1.downcast_ref()
+            }
+        }
+    }
+
+    //The sampling helpers can't return anything sensible for a bad handle
+    fn texture(&self, handle: Handle<Texture>) -> &'a Texture {
+        self.get_texture(handle).expect("Invalid texture handle!")
+    }
+
+    fn sampler(&self, handle: Handle<Sampler>) -> &'a Sampler {
+        self.get_sampler(handle).expect("Invalid sampler handle!")
+    }
+
+    /// Samples a texture with a sampler, both by handle.
+    /// Panics if either handle is invalid, like every sampling helper.
+    pub fn sample_2d(
+        &self,
+        texture_handle: Handle<Texture>,
+        sampler_handle: Handle<Sampler>,
+        uv: Vec2,
+    ) -> Vec4 {
+        self.sampler(sampler_handle)
+            .sample(self.texture(texture_handle), uv)
+    }
+
+    /// Samples a texture with a sampler, picking the mip level from the
+    /// screen space derivatives of the texture coordinates.
+    pub fn sample_2d_grad(
+        &self,
+        texture_handle: Handle<Texture>,
+        sampler_handle: Handle<Sampler>,
+        uv: Vec2,
+        derivatives: &FragmentDerivatives<Vec2>,
+    ) -> Vec4 {
+        self.sampler(sampler_handle).sample_grad(
+            self.texture(texture_handle),
+            uv,
+            derivatives.ddx,
+            derivatives.ddy,
+        )
+    }
+
+    /// Samples one layer of a texture array with a sampler, both by handle.
+    pub fn sample_2d_array(
+        &self,
+        texture_handle: Handle<Texture>,
+        sampler_handle: Handle<Sampler>,
+        uv: Vec2,
+        layer: u32,
+    ) -> Vec4 {
+        self.sampler(sampler_handle)
+            .sample_array(self.texture(texture_handle), uv, layer)
+    }
+
+    /// Samples a cubemap in the given direction with a sampler, both by handle.
+    pub fn sample_cube(
+        &self,
+        texture_handle: Handle<Texture>,
+        sampler_handle: Handle<Sampler>,
+        direction: Vec3,
+    ) -> Vec4 {
+        self.sampler(sampler_handle)
+            .sample_cube(self.texture(texture_handle), direction)
+    }
+
+    /// Samples a 3D texture with a sampler, both by handle.
+    pub fn sample_3d(
+        &self,
+        texture_handle: Handle<Texture>,
+        sampler_handle: Handle<Sampler>,
+        uvw: Vec3,
+    ) -> Vec4 {
+        self.sampler(sampler_handle)
+            .sample_3d(self.texture(texture_handle), uvw)
+    }
+
+    /// Compares the reference depth against a depth texture with a comparison
+    /// sampler, both by handle. Returns how much of the sample passed, from 0 to 1.
+    pub fn sample_2d_compare(
+        &self,
+        texture_handle: Handle<Texture>,
+        sampler_handle: Handle<Sampler>,
+        uv: Vec2,
+        reference: f32,
+    ) -> f32 {
+        self.sampler(sampler_handle)
+            .sample_compare(self.texture(texture_handle), uv, reference)
+    }
 }
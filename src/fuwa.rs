@@ -1,58 +1,57 @@
-use super::Texture;
+use crate::{lane_color, FragmentBatch, FragmentShaderSimd, SimdShader};
 use crate::{
-    rasterization::{FragmentBuffer, FragmentKey, FragmentSlabMap, SlabPtr},
-    render_pipeline::DepthBuffer,
-    FSInput,
+    rasterization::{DrawKey, Fragment, FragmentBuffer, FragmentKey, FragmentSlabMap, SlabPtr},
+    render_pipeline::{DepthBuffer, DepthState, StencilBuffer, StencilState},
+    FSInput, FSInputSimd,
 };
-use crate::{FragmentShader, Uniforms};
+use crate::{AnyResource, Handle, HandleMap};
+use crate::{BlendState, ColorWriteMask, CullMode, RenderTarget, ScissorRect, Viewport};
+use crate::{FragmentOutput, FragmentShader};
+use crate::{RenderTexture, TextureFormat, UniformStorage};
+use bytemuck::cast;
 use glam::*;
-use image::GenericImageView;
-use lazy_static::lazy_static;
 use pixels::wgpu::{PowerPreference, RequestAdapterOptions};
-use pixels::{Error, Pixels, PixelsBuilder, SurfaceTexture};
+use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
 use raw_window_handle::HasRawWindowHandle;
 use rayon::prelude::*;
 use std::marker::{Send, Sync};
 use wide::f32x8;
 
-pub struct Fuwa<W: HasRawWindowHandle> {
+pub struct Fuwa<T: RenderTarget> {
     pub width: u32,
     pub height: u32,
     pub pixel_count: u32,
-    pub x_factor: f32,
-    pub y_factor: f32,
-    pub pixels: Pixels<W>,
+    pub(crate) viewport: Viewport,
+    pub(crate) scissor: Option<ScissorRect>,
+    pub target: T,
     pub(crate) depth_buffer: DepthBuffer,
+    pub(crate) depth_state: DepthState,
+    pub(crate) stencil_buffer: StencilBuffer,
+    pub(crate) stencil_state: StencilState,
+    pub(crate) cull_mode: CullMode,
+    pub(crate) blend_state: Option<BlendState>,
+    pub(crate) color_write_mask: ColorWriteMask,
+    //Blend states and write masks of the draws waiting to be rendered
+    pub(crate) draw_color_states: Vec<(Option<BlendState>, ColorWriteMask)>,
+    pub(crate) alpha_test: Option<f32>,
+    pub(crate) framebuffer_format: TextureFormat,
+    pub(crate) render_textures: HandleMap<RenderTexture>,
+    pub(crate) bound_render_texture: Option<Handle<RenderTexture>>,
+    pub(crate) shaders: HandleMap<AnyResource>,
+    pub(crate) vertex_buffers: HandleMap<AnyResource>,
     pub(crate) fragment_buffer: FragmentBuffer,
     pub fragment_slab_map: FragmentSlabMap,
-    pub(crate) uniforms: Uniforms,
-    pub(crate) thread_count: usize, //Do i need this?
-}
-
-lazy_static! {
-    static ref THREAD_COLOR: [&'static [u8; 4]; 8] = [
-        &super::colors::RED,
-        &super::colors::GREEN,
-        &super::colors::BLUE,
-        &super::colors::CYAN,
-        &super::colors::MAGENTA,
-        &super::colors::YELLOW,
-        &super::colors::PINK,
-        &super::colors::OFFWHITE,
-    ];
+    pub(crate) uniforms: UniformStorage,
+    pub(crate) thread_count: usize,
 }
 
 #[derive(Copy, Clone)]
-pub(crate) struct FuwaPtr<W: HasRawWindowHandle>(pub(crate) *mut Fuwa<W>);
+pub(crate) struct FuwaPtr<T: RenderTarget>(pub(crate) *mut Fuwa<T>);
 
-unsafe impl<W: HasRawWindowHandle> Send for FuwaPtr<W> {}
-unsafe impl<W: HasRawWindowHandle> Sync for FuwaPtr<W> {}
-
-impl<W: HasRawWindowHandle + Send + Sync> Fuwa<W> {
-    pub(crate) fn get_self_ptr(&mut self) -> FuwaPtr<W> {
-        FuwaPtr(self as *mut Self)
-    }
+unsafe impl<T: RenderTarget> Send for FuwaPtr<T> {}
+unsafe impl<T: RenderTarget> Sync for FuwaPtr<T> {}
 
+impl<W: HasRawWindowHandle + Send + Sync> Fuwa<Pixels<W>> {
     pub fn new(
         width: u32,
         height: u32,
@@ -61,63 +60,174 @@ impl<W: HasRawWindowHandle + Send + Sync> Fuwa<W> {
         high_performance: Option<bool>,
         window: &W,
     ) -> Self {
+        let pixels = PixelsBuilder::new(width, height, SurfaceTexture::new(width, height, window))
+            .enable_vsync(vsync)
+            .request_adapter_options(RequestAdapterOptions {
+                power_preference: match high_performance {
+                    None => PowerPreference::Default,
+                    Some(true) => PowerPreference::HighPerformance,
+                    Some(false) => PowerPreference::LowPower,
+                },
+                compatible_surface: None,
+            })
+            .build()
+            .unwrap();
+
+        Self::with_target(width, height, thread_count, pixels)
+    }
+}
+
+impl Fuwa<Vec<u8>> {
+    /// Creates a Fuwa which renders into a CPU side buffer instead of a window.
+    pub fn new_offscreen(width: u32, height: u32, thread_count: usize) -> Self {
+        Self::with_target(
+            width,
+            height,
+            thread_count,
+            vec![0; (width * height * 4) as usize],
+        )
+    }
+}
+
+impl<T: RenderTarget> Fuwa<T> {
+    pub(crate) fn get_self_ptr(&mut self) -> FuwaPtr<T> {
+        FuwaPtr(self as *mut Self)
+    }
+
+    pub fn with_target(width: u32, height: u32, thread_count: usize, mut target: T) -> Self {
+        assert_eq!(
+            target.get_frame().len(),
+            (width * height * 4) as usize,
+            "Render target frame does not match the given dimensions!"
+        );
+
         Self {
             width,
             height,
             pixel_count: width * height,
             depth_buffer: DepthBuffer::new(width, height),
+            depth_state: DepthState::default(),
+            stencil_buffer: StencilBuffer::new(width, height),
+            stencil_state: StencilState::default(),
+            cull_mode: CullMode::Back,
+            blend_state: None,
+            color_write_mask: ColorWriteMask::ALL,
+            draw_color_states: Vec::new(),
+            alpha_test: None,
+            framebuffer_format: TextureFormat::Rgba8Unorm,
+            render_textures: HandleMap::new(),
+            bound_render_texture: None,
+            shaders: HandleMap::new(),
+            vertex_buffers: HandleMap::new(),
             thread_count,
-            x_factor: width as f32 * 0.5,
-            y_factor: height as f32 * 0.5,
-            uniforms: Uniforms::new(),
+            viewport: Viewport::new(0., 0., width as f32, height as f32),
+            scissor: None,
+            uniforms: UniformStorage::new(),
             fragment_buffer: FragmentBuffer::new(width, height),
             fragment_slab_map: FragmentSlabMap::new(),
             //fuwa_data: FuwaData::new(),
-            pixels: PixelsBuilder::new(width, height, SurfaceTexture::new(width, height, &*window))
-                .enable_vsync(vsync)
-                .request_adapter_options(RequestAdapterOptions {
-                    power_preference: match high_performance {
-                        None => PowerPreference::Default,
-                        Some(true) => PowerPreference::HighPerformance,
-                        Some(false) => PowerPreference::LowPower,
-                    },
-                    compatible_surface: None,
-                })
-                .build()
-                .unwrap(),
+            target,
         }
     }
 
+    pub fn get_thread_count(&self) -> usize {
+        self.thread_count
+    }
+
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        self.viewport = viewport;
+    }
+
+    pub fn get_viewport(&self) -> &Viewport {
+        &self.viewport
+    }
+
+    /// Restricts rasterization to the given rectangle, or the
+    /// whole framebuffer if None.
+    pub fn set_scissor(&mut self, scissor: Option<ScissorRect>) {
+        self.scissor = scissor;
+    }
+
+    pub fn get_scissor(&self) -> Option<&ScissorRect> {
+        self.scissor.as_ref()
+    }
+
+    pub fn get_frame(&mut self) -> &mut [u8] {
+        self.target.get_frame()
+    }
+
+    pub fn into_target(self) -> T {
+        self.target
+    }
+
+    //A color attachment of the bound render texture, or the render target's frame
+    //as the only attachment. None if there's no attachment at the index.
+    pub(crate) fn get_color_buffer(&mut self, attachment: usize) -> Option<&mut [u8]> {
+        match self.bound_render_texture {
+            Some(handle) => self.render_textures[handle]
+                .color_data
+                .get_mut(attachment)
+                .map(Vec::as_mut_slice),
+            None if attachment == 0 => Some(self.target.get_frame()),
+            None => None,
+        }
+    }
+
+    pub(crate) fn color_attachment_count(&self) -> usize {
+        match self.bound_render_texture {
+            Some(handle) => self.render_textures[handle].colors.len(),
+            None => 1,
+        }
+    }
+
+    pub(crate) fn color_attachment_format(&self, attachment: usize) -> TextureFormat {
+        match self.bound_render_texture {
+            Some(handle) => self.render_textures[handle].color_formats[attachment],
+            None => self.framebuffer_format,
+        }
+    }
+
+    //Raw RGBA8 colors are written as is to RGBA8 formats, and converted for anything else
+    fn encode_raw_color(format: TextureFormat, color: &[u8; 4]) -> ([u8; 16], usize) {
+        let mut texel = [0; 16];
+        match format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => {
+                texel[..4].copy_from_slice(color)
+            }
+            _ => format.encode(TextureFormat::Rgba8Unorm.decode(color), &mut texel),
+        }
+        (texel, format.bytes_per_texel())
+    }
+
+    /// Fills every color attachment with the color.
     pub fn clear_color(&mut self, color: &[u8; 4]) {
-        let color_bytes: [u8; 64] = [
-            color[0], color[1], color[2], color[3], color[0], color[1], color[2], color[3],
-            color[0], color[1], color[2], color[3], color[0], color[1], color[2], color[3],
-            color[0], color[1], color[2], color[3], color[0], color[1], color[2], color[3],
-            color[0], color[1], color[2], color[3], color[0], color[1], color[2], color[3],
-            color[0], color[1], color[2], color[3], color[0], color[1], color[2], color[3],
-            color[0], color[1], color[2], color[3], color[0], color[1], color[2], color[3],
-            color[0], color[1], color[2], color[3], color[0], color[1], color[2], color[3],
-            color[0], color[1], color[2], color[3], color[0], color[1], color[2], color[3],
-        ];
-        self.pixels
-            .get_frame()
-            .par_chunks_mut(4 * 16)
-            .for_each(|pixel_chunk| {
-                pixel_chunk.copy_from_slice(&color_bytes);
-            })
+        self.mark_render_texture_drawn();
+        for attachment in 0..self.color_attachment_count() {
+            let (texel, size) =
+                Self::encode_raw_color(self.color_attachment_format(attachment), color);
+            if let Some(buffer) = self.get_color_buffer(attachment) {
+                buffer.par_chunks_exact_mut(size).for_each(|pixel| {
+                    pixel.copy_from_slice(&texel[..size]);
+                })
+            }
+        }
     }
 
     pub fn clear_all(&mut self) {
         self.clear();
         self.depth_buffer.clear();
+        self.stencil_buffer.clear();
     }
 
     pub fn clear(&mut self) {
+        self.mark_render_texture_drawn();
         //TODO: Is this faster than parallel?
-        unsafe {
-            let target = self.pixels.get_frame().as_mut_ptr();
-            let len = self.pixels.get_frame().len();
-            std::ptr::write_bytes(target, 0, len);
+        for attachment in 0..self.color_attachment_count() {
+            if let Some(buffer) = self.get_color_buffer(attachment) {
+                unsafe {
+                    std::ptr::write_bytes(buffer.as_mut_ptr(), 0, buffer.len());
+                }
+            }
         }
         // let frame = self.pixels.get_frame();
         // let step = frame.len() / self.thread_count;
@@ -127,27 +237,271 @@ impl<W: HasRawWindowHandle + Send + Sync> Fuwa<W> {
     }
 
     pub fn clear_depth_buffer(&mut self) {
+        self.mark_render_texture_drawn();
         self.depth_buffer.clear();
     }
 
+    pub fn clear_stencil_buffer(&mut self) {
+        self.stencil_buffer.clear();
+    }
+
+    /// Sets the depth tests and writes used by subsequent draws.
+    pub fn set_depth_state(&mut self, depth_state: DepthState) {
+        self.depth_state = depth_state;
+    }
+
+    pub fn get_depth_state(&self) -> &DepthState {
+        &self.depth_state
+    }
+
+    /// Sets the value the depth buffer is reset to when cleared.
+    pub fn set_depth_clear_value(&mut self, clear_value: f32) {
+        self.depth_buffer.clear_value = clear_value;
+    }
+
+    /// Sets the stencil test and operations used by subsequent draws.
+    pub fn set_stencil_state(&mut self, stencil_state: StencilState) {
+        self.stencil_state = stencil_state;
+    }
+
+    pub fn get_stencil_state(&self) -> &StencilState {
+        &self.stencil_state
+    }
+
+    /// Sets the value the stencil buffer is reset to when cleared.
+    pub fn set_stencil_clear_value(&mut self, clear_value: u8) {
+        self.stencil_buffer.clear_value = clear_value;
+    }
+
+    /// Sets which triangles are thrown away before rasterization, based on their facing.
+    pub fn set_cull_mode(&mut self, cull_mode: CullMode) {
+        self.cull_mode = cull_mode;
+    }
+
+    pub fn get_cull_mode(&self) -> CullMode {
+        self.cull_mode
+    }
+
+    /// Sets how the fragments of subsequent draws are combined with
+    /// the framebuffer, or overwrite it if None. Blended draws get shaded
+    /// as they're drawn, on top of whatever has been rendered so far.
+    pub fn set_blend_state(&mut self, blend_state: Option<BlendState>) {
+        self.blend_state = blend_state;
+    }
+
+    pub fn get_blend_state(&self) -> Option<&BlendState> {
+        self.blend_state.as_ref()
+    }
+
+    /// Sets which channels the fragments of subsequent draws write to.
+    /// Like blending, anything but ColorWriteMask::ALL shades fragments as they're drawn.
+    pub fn set_color_write_mask(&mut self, color_write_mask: ColorWriteMask) {
+        self.color_write_mask = color_write_mask;
+    }
+
+    pub fn get_color_write_mask(&self) -> &ColorWriteMask {
+        &self.color_write_mask
+    }
+
+    //Blended and masked fragments depend on what's under them, which render can't
+    //provide, as only the last fragment drawn at each pixel waits for it
+    pub(crate) fn reads_framebuffer(&self) -> bool {
+        self.blend_state
+            .is_some_and(|blend_state| blend_state != BlendState::replace())
+            || self.color_write_mask != ColorWriteMask::ALL
+    }
+
+    /// Discards fragments with an alpha below the threshold (0.0 - 1.0)
+    /// during subsequent draws, or disables the test if None.
+    pub fn set_alpha_test(&mut self, threshold: Option<f32>) {
+        self.alpha_test = threshold;
+    }
+
+    pub fn get_alpha_test(&self) -> Option<f32> {
+        self.alpha_test
+    }
+
+    /// Sets how fragment shader output is encoded into the window's framebuffer.
+    /// Rgba8UnormSrgb converts the linear colors to sRGB, and blends in linear space.
+    /// Render textures keep the formats they were created with, so this panics while one is bound.
+    pub fn set_framebuffer_format(&mut self, format: TextureFormat) {
+        assert!(
+            self.bound_render_texture.is_none(),
+            "The framebuffer format can't be changed while a render texture is bound!"
+        );
+        assert!(
+            matches!(
+                format,
+                TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+            ),
+            "The framebuffer only supports RGBA8 formats!"
+        );
+        self.framebuffer_format = format;
+    }
+
+    /// The window's format, or that of the bound render texture's first color attachment.
+    pub fn get_framebuffer_format(&self) -> TextureFormat {
+        self.framebuffer_format
+    }
+
+    /// Runs the stencil and depth tests for a front facing fragment at the pixel,
+    /// applying the stencil operations and depth write. Returns whether it passed both.
     pub fn try_set_depth(&mut self, x: u32, y: u32, depth: f32) -> bool {
-        self.depth_buffer
-            .try_set_depth((x + y * self.width) as usize, depth)
+        let index = (x + y * self.width) as usize;
+        if !self.stencil_state.enabled {
+            return self
+                .depth_buffer
+                .try_set_depth(index, depth, &self.depth_state);
+        }
+
+        let state = self.stencil_state;
+        let stencil_pass = self.stencil_buffer.test(index, &state, &state.front);
+        let depth_pass = stencil_pass
+            && self
+                .depth_buffer
+                .try_set_depth(index, depth, &self.depth_state);
+        self.stencil_buffer
+            .update(index, (stencil_pass, depth_pass), &state, &state.front);
+
+        depth_pass
     }
 
     pub fn try_set_depth_simd(&mut self, x: u32, y: u32, depths: &f32x8) -> Option<f32x8> {
-        self.depth_buffer
-            .try_set_depth_simd((x + y * self.width) as usize, depths)
+        self.depth_buffer.try_set_depth_simd(
+            (x + y * self.width) as usize,
+            depths,
+            &self.depth_state,
+        )
+    }
+
+    //Runs the stencil test, then the depth test on whatever passed,
+    //before applying the stencil operations to every covered lane.
+    //Without writes, depth is left alone and only the lanes failing a test
+    //get their stencil operations, so write_depth_stencil_simd can finish
+    //off whichever lanes the fragment shader keeps.
+    pub(crate) fn try_set_depth_stencil_simd(
+        &mut self,
+        x: u32,
+        y: u32,
+        depths: &f32x8,
+        front_facing: bool,
+        write: bool,
+    ) -> Option<f32x8> {
+        let index = (x + y * self.width) as usize;
+        let depth_state = DepthState {
+            write_enabled: write && self.depth_state.write_enabled,
+            ..self.depth_state
+        };
+
+        if !self.stencil_state.enabled {
+            return self
+                .depth_buffer
+                .try_set_depth_simd(index, depths, &depth_state);
+        }
+
+        let state = self.stencil_state;
+        let face = state.get_face(front_facing);
+        let coverage_mask = depths.cmp_eq(*depths).move_mask();
+
+        let stencil_pass = self
+            .stencil_buffer
+            .test_simd(index, coverage_mask, &state, face);
+        let mut stencil_pass_lanes = [0u32; 8];
+        for (lane, value) in stencil_pass_lanes.iter_mut().enumerate() {
+            if stencil_pass & 1 << lane != 0 {
+                *value = u32::MAX;
+            }
+        }
+        let depths = cast::<_, f32x8>(stencil_pass_lanes).blend(*depths, f32x8::splat(f32::NAN));
+
+        let depth_pass = self
+            .depth_buffer
+            .try_set_depth_simd(index, &depths, &depth_state);
+        let depth_pass_mask = depth_pass.map_or(0, |depth_pass| depth_pass.move_mask());
+        let update_mask = if write {
+            coverage_mask
+        } else {
+            coverage_mask & !depth_pass_mask
+        };
+        self.stencil_buffer.update_simd(
+            index,
+            update_mask,
+            (stencil_pass, depth_pass_mask),
+            &state,
+            face,
+        );
+
+        depth_pass
+    }
+
+    //Writes the depth and applies the stencil pass operation for every kept lane
+    pub(crate) fn write_depth_stencil_simd(
+        &mut self,
+        x: u32,
+        y: u32,
+        depths: &f32x8,
+        front_facing: bool,
+        keep: i32,
+    ) {
+        let index = (x + y * self.width) as usize;
+        let depths = cast::<_, [f32; 8]>(*depths);
+        let depth_state = self.depth_state;
+        let stencil_state = self.stencil_state;
+
+        for lane in (0..8).filter(|lane| keep & 1 << lane != 0) {
+            if depth_state.test_enabled && depth_state.write_enabled {
+                self.depth_buffer.depth_buffer[index + lane] = depths[lane];
+            }
+            if stencil_state.enabled {
+                self.stencil_buffer.update(
+                    index + lane,
+                    (true, true),
+                    &stencil_state,
+                    stencil_state.get_face(front_facing),
+                );
+            }
+        }
     }
 
     pub(crate) fn set_fragment(&mut self, x: u32, y: u32, frag: FragmentKey) {
-        self.fragment_buffer
-            .set_fragment((x + y * self.width) as usize, frag);
+        self.fragment_buffer.set_fragment(
+            (x + y * self.width) as usize,
+            frag,
+            &self.fragment_slab_map,
+        );
+    }
+
+    //Index of the current blend state and write mask, reusing an earlier draw's if they match
+    pub(crate) fn snapshot_color_state(&mut self) -> u32 {
+        let state = (self.blend_state, self.color_write_mask);
+        match self.draw_color_states.iter().position(|s| *s == state) {
+            Some(index) => index as u32,
+            None => {
+                self.draw_color_states.push(state);
+                (self.draw_color_states.len() - 1) as u32
+            }
+        }
+    }
+
+    //Forgets every draw's bindings and color state once no fragments refer to them
+    pub(crate) fn reset_draw_states(&mut self) {
+        if !self.has_pending_fragments() {
+            self.uniforms.clear_draw_bindings();
+            self.draw_color_states.clear();
+        }
     }
 
-    pub fn render<F: FSInput>(&mut self, shader: &impl FragmentShader<F>, shader_index: usize) {
+    /// Shades every fragment drawn with the shader since it was last rendered.
+    /// Shaders which can discard, and draws made with the alpha test, blending
+    /// or a color write mask, get shaded as they're drawn instead, leaving nothing to render.
+    pub fn render<F: FSInput, S: FragmentShader<F> + 'static>(&mut self, handle: Handle<S>) {
+        self.mark_render_texture_drawn();
         unsafe {
             let self_ptr = self.get_self_ptr();
+            let shader = (*self_ptr.0)
+                .get_fragment_shader(handle)
+                .expect("Invalid fragment shader handle!");
+            let shader_handle = handle.cast::<AnyResource>();
             let slab = (*self_ptr.0).fragment_slab_map.get_mut_slab::<F>();
             (*self_ptr.0)
                 .fragment_buffer
@@ -157,25 +511,110 @@ impl<W: HasRawWindowHandle + Send + Sync> Fuwa<W> {
                 .filter(|(_index, fragment)| fragment.is_some())
                 .for_each(|(index, fragment)| {
                     let frag = fragment.as_ref().unwrap();
-                    if frag.shader_index == shader_index {
-                        let color = shader.fragment_shader_fn(
-                            slab.take(frag.fragment_key).unwrap(),
-                            &(*self_ptr.0).uniforms,
-                        );
-                        (*self_ptr.0).set_pixel_by_index(index << 2, &color);
+                    if frag.draw_key.shader == shader_handle {
+                        let input = slab.take(frag.fragment_key).unwrap();
+                        if let Some(output) = shader.fragment_shader_fn(
+                            input.input,
+                            &input.context,
+                            &(*self_ptr.0).uniforms.for_draw(frag.draw_key.bindings),
+                        ) {
+                            (*self_ptr.0).write_fragment_output(
+                                index,
+                                &output,
+                                frag.draw_key.color_state,
+                            );
+                        }
                         *fragment = None;
                     }
                 });
         }
+        self.fragment_buffer.refresh_pending();
+        self.reset_draw_states();
     }
 
-    pub fn present(&mut self) -> Result<(), Error> {
-        self.pixels.render()
+    /// Shades every fragment drawn with the shader since it was last rendered,
+    /// 8 neighbouring pixels at a time. Pixels drawn with different uniform
+    /// bindings end up in separate batches.
+    pub fn render_simd<F: FSInputSimd, S: FragmentShaderSimd<F> + 'static>(
+        &mut self,
+        handle: Handle<SimdShader<S>>,
+    ) {
+        self.mark_render_texture_drawn();
+        unsafe {
+            let self_ptr = self.get_self_ptr();
+            let shader = &(*self_ptr.0)
+                .get_fragment_shader(handle)
+                .expect("Invalid fragment shader handle!")
+                .0;
+            let shader_handle = handle.cast::<AnyResource>();
+            let slab = (*self_ptr.0).fragment_slab_map.get_mut_slab::<F>();
+            (*self_ptr.0)
+                .fragment_buffer
+                .get_fragments_view_mut()
+                .par_chunks_mut(8)
+                .enumerate()
+                .for_each(|(chunk, fragments)| {
+                    //One batch per set of bindings the pixels were drawn with
+                    while let Some(draw_key) = fragments
+                        .iter()
+                        .flatten()
+                        .find(|frag| frag.draw_key.shader == shader_handle)
+                        .map(|frag| frag.draw_key)
+                    {
+                        let mut batch = [None; 8];
+                        for (lane, fragment) in fragments.iter().enumerate() {
+                            if let Some(frag) = fragment.as_ref().filter(|f| f.draw_key == draw_key)
+                            {
+                                batch[lane] = Some(slab.take(frag.fragment_key).unwrap());
+                            }
+                        }
+
+                        let (color, keep) = shader.fragment_shader_simd_fn(
+                            &FragmentBatch::new(&batch),
+                            &(*self_ptr.0).uniforms.for_draw(draw_key.bindings),
+                        );
+                        let keep = keep.move_mask();
+                        for (lane, fragment) in fragments.iter_mut().enumerate() {
+                            if batch[lane].is_none() {
+                                continue;
+                            }
+
+                            *fragment = None;
+                            if keep & 1 << lane != 0 {
+                                (*self_ptr.0).write_fragment_output(
+                                    chunk * 8 + lane,
+                                    &lane_color(&color, lane),
+                                    draw_key.color_state,
+                                );
+                            }
+                        }
+                    }
+                });
+        }
+        self.fragment_buffer.refresh_pending();
+        self.reset_draw_states();
+    }
+
+    //Tests the alpha of the first color, so outputs without any always pass
+    fn passes_alpha_test<O: FragmentOutput>(&self, output: &O) -> bool {
+        O::COUNT == 0
+            || self
+                .alpha_test
+                .is_none_or(|threshold| output.color(0).w() >= threshold)
     }
 
-    //TODO: Use 0 for depth min sice we do 1/z
-    pub fn render_depth_buffer(&mut self) -> Result<(), Error> {
-        let pixel_iter = self.pixels.get_frame().par_chunks_exact_mut(4);
+    pub fn present(&mut self) -> Result<(), T::Error> {
+        self.target.present()
+    }
+
+    /// Shows the depth buffer in grayscale and presents it. Only works on the window,
+    /// so panics while a render texture is bound.
+    pub fn render_depth_buffer(&mut self) -> Result<(), T::Error> {
+        assert!(
+            self.bound_render_texture.is_none(),
+            "The depth buffer can't be rendered while a render texture is bound!"
+        );
+        let pixel_iter = self.target.get_frame().par_chunks_exact_mut(4);
         let mut depth_max = f32::NEG_INFINITY;
         let mut depth_min = f32::INFINITY;
 
@@ -194,7 +633,7 @@ impl<W: HasRawWindowHandle + Send + Sync> Fuwa<W> {
 
         if range != 0. {
             depth_iter.for_each(|x| {
-                *x = u8::MAX as f32 - ((*x - depth_min) / range * u8::MAX as f32);
+                *x = (*x - depth_min) / range * u8::MAX as f32;
             });
         } else {
             depth_iter.for_each(|x| {
@@ -210,35 +649,85 @@ impl<W: HasRawWindowHandle + Send + Sync> Fuwa<W> {
             pixel.copy_from_slice(&[color, color, color, 0xFF]);
         });
 
-        self.pixels.render()
+        self.target.present()
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
-        self.pixels.resize(width, height);
+        self.target.resize(width, height);
         self.pixel_count = width * height;
     }
 
+    /// Writes the color to the window, or the first color attachment of
+    /// the bound render texture, converted to its format.
     pub fn set_pixel(&mut self, x: u32, y: u32, color: &[u8; 4]) {
-        let index = self.pos_to_index(x, y);
-
         assert!(
-            (index as u32) < self.pixel_count * 4,
+            x < self.width && y < self.height,
             "Pixel drawn out of bounds!"
         );
-
-        self.set_pixel_by_index(index, color)
+        self.mark_render_texture_drawn();
+        self.set_pixel_by_index(self.pos_to_index(x, y), color)
     }
 
+    //Byte index into the color buffer
     pub(crate) fn pos_to_index(&self, x: u32, y: u32) -> usize {
-        4 * (x + (y * self.width)) as usize
+        self.framebuffer_format.bytes_per_texel() * (x + (y * self.width)) as usize
     }
 
     pub(crate) fn set_pixel_by_index(&mut self, index: usize, color: &[u8; 4]) {
-        unsafe {
-            self.pixels
-                .get_frame()
-                .get_unchecked_mut(index..index + 4)
-                .copy_from_slice(color);
+        let (texel, size) = Self::encode_raw_color(self.framebuffer_format, color);
+        if let Some(buffer) = self.get_color_buffer(0) {
+            unsafe {
+                buffer
+                    .get_unchecked_mut(index..index + size)
+                    .copy_from_slice(&texel[..size]);
+            }
+        }
+    }
+
+    //Writes every color of the fragment shader's output which has an attachment to go to
+    pub(crate) fn write_fragment_output<O: FragmentOutput>(
+        &mut self,
+        pixel: usize,
+        output: &O,
+        color_state: u32,
+    ) {
+        let (blend_state, color_write_mask) = self.draw_color_states[color_state as usize];
+        for attachment in 0..O::COUNT.min(self.color_attachment_count()) {
+            self.write_pixel_by_index(
+                attachment,
+                pixel,
+                output.color(attachment),
+                (blend_state.as_ref(), color_write_mask),
+            );
+        }
+    }
+
+    //Writes a color to an attachment, taking blending, the write mask
+    //and the attachment's format into account
+    pub(crate) fn write_pixel_by_index(
+        &mut self,
+        attachment: usize,
+        pixel: usize,
+        color: Vec4,
+        (blend_state, color_write_mask): (Option<&BlendState>, ColorWriteMask),
+    ) {
+        let format = self.color_attachment_format(attachment);
+        let size = format.bytes_per_texel();
+        let index = pixel * size;
+
+        if let Some(buffer) = self.get_color_buffer(attachment) {
+            let pixel = unsafe { buffer.get_unchecked_mut(index..index + size) };
+
+            if blend_state.is_none() && color_write_mask == ColorWriteMask::ALL {
+                return format.encode(color, pixel);
+            }
+
+            let dst = format.decode(pixel);
+            let color = match blend_state {
+                Some(blend_state) => blend_state.blend(color, dst),
+                None => color,
+            };
+            format.encode(color_write_mask.apply(color, dst), pixel);
         }
     }
 
@@ -255,6 +744,8 @@ impl<W: HasRawWindowHandle + Send + Sync> Fuwa<W> {
     pub fn draw_line(&mut self, mut start: Vec3A, mut end: Vec3A, color: &[u8; 4]) {
         use std::ptr::swap;
 
+        self.mark_render_texture_drawn();
+
         assert!(
             self.check_3d_pixel_within_bounds(&start) && self.check_3d_pixel_within_bounds(&end),
             "Line drawn out of bounds."
@@ -297,21 +788,31 @@ impl<W: HasRawWindowHandle + Send + Sync> Fuwa<W> {
 
     pub fn transform_screen_space_perspective<F: FSInput>(
         &self,
-        point: &mut Vec3A,
+        point: &mut Vec4,
         interpolant: &mut F,
     ) {
-        let z_inverse = point.z().recip();
-
-        *interpolant *= z_inverse;
-
-        *point.x_mut() = ((point.x() * z_inverse) + 1.) * self.x_factor;
-        *point.y_mut() = ((-point.y() * z_inverse) + 1.) * self.y_factor;
-        *point.z_mut() = z_inverse;
+        let w_inverse = point.w().recip();
+
+        //Perspective correct interpolants are interpolated divided by w, and multiplied
+        //back per pixel. Both are skipped for linear and flat ones.
+        *interpolant = interpolant.scale_by_mode(w_inverse, 1.);
+
+        let viewport = &self.viewport;
+        *point.x_mut() = viewport.x + ((point.x() * w_inverse) + 1.) * viewport.width * 0.5;
+        *point.y_mut() = viewport.y + ((-point.y() * w_inverse) + 1.) * viewport.height * 0.5;
+        *point.z_mut() = viewport.min_depth
+            + (point.z() * w_inverse) * (viewport.max_depth - viewport.min_depth);
+        *point.w_mut() = w_inverse;
     }
 
+    /// Maps the x and y of a point in normalized device coordinates to the viewport.
+    #[deprecated(
+        note = "the pipeline now transforms the clip space positions from vertex shaders itself"
+    )]
     pub fn transform_screen_space_orthographic(&self, point: &mut Vec3A) {
-        *point.x_mut() = (point.x() + 1.) * self.x_factor;
-        *point.y_mut() = (-point.y() + 1.) * self.y_factor;
+        let viewport = &self.viewport;
+        *point.x_mut() = viewport.x + (point.x() + 1.) * viewport.width * 0.5;
+        *point.y_mut() = viewport.y + (-point.y() + 1.) * viewport.height * 0.5;
     }
 
     fn check_3d_pixel_within_bounds(&self, pos: &Vec3A) -> bool {
@@ -326,119 +827,57 @@ impl<W: HasRawWindowHandle + Send + Sync> Fuwa<W> {
         self.set_pixel_by_index(self.pos_to_index(x, y), color)
     }
 
-    pub(crate) fn try_set_depth_block(
-        &mut self,
-        (block_x, block_y): (u32, u32),
-        (width, height): (u32, u32),
-        depths: Vec<f32>,
-    ) -> Option<Vec<Option<f32>>> {
-        //optick::event!();
-        let mut output = Vec::with_capacity((width * height) as usize);
-        let mut idx = 0;
-
-        unsafe {
-            for y in block_y..block_y + height {
-                let y_offset = y * self.width;
-                let prev = self.depth_buffer.depth_buffer.get_unchecked_mut(
-                    (y_offset + block_x) as usize..(y_offset + block_x + width) as usize,
-                );
-                for prev_value in prev.iter_mut() {
-                    if depths[idx] > *prev_value {
-                        *prev_value = depths[idx];
-                        output.push(Some(depths[idx]));
-                    } else {
-                        output.push(None);
-                    }
-                    idx += 1;
-                }
-            }
-        }
-
-        if !output.is_empty() {
-            Some(output)
-        } else {
-            None
-        }
-    }
-
-    pub(crate) fn set_fragments_block<F: FSInput>(
-        &mut self,
-        (block_x, block_y): (u32, u32),
-        (block_width, block_height): (u32, u32),
-        depth_pass: &[Option<f32>],
-        interp: Vec<F>,
-        fs_index: usize,
-        slab_ptr: SlabPtr<F>,
-    ) {
-        let mut idx = 0;
-        let mut interp = interp.into_iter();
-        for y in block_y..block_y + block_height {
-            for x in block_x..block_x + block_width {
-                if depth_pass[idx].is_some() {
-                    let frag = slab_ptr.insert_fragment(fs_index, interp.next().unwrap());
-                    self.set_fragment(x, y, frag);
-                }
-                idx += 1;
-            }
-        }
-    }
-
     pub(crate) fn set_fragments_simd<F: FSInput>(
         &mut self,
         pixel_x: u32,
         pixel_y: u32,
-        interp: [F; 8],
+        interp: [Fragment<F>; 8],
         depth_pass: f32x8,
-        fs_index: usize,
+        draw_key: DrawKey,
         slab_ptr: SlabPtr<F>,
     ) {
         let depth_pass = depth_pass.move_mask();
         for pixel in 0..8 {
             if 1 << pixel & depth_pass != 0 {
-                let frag = slab_ptr.insert_fragment(fs_index, interp[pixel as usize]);
+                let frag = slab_ptr.insert_fragment(draw_key, interp[pixel as usize]);
                 self.set_fragment(pixel_x + pixel, pixel_y, frag)
             }
         }
     }
 
-    // pub(crate) fn set_pixels_block(
-    //     &mut self,
-    //     (block_x, block_y): (u32, u32),
-    //     (width, height): (u32, u32),
-    //     depth_pass: &[Option<f32>],
-    //     interp: Vec<Vec<f32>>,
-    //     fragment_shader: &FragmentShaderFunction,
-    // ) {
-    //     //optick::event!();
-    //     let mut idx = 0;
-    //     for y in block_y..block_y + height {
-    //         for x in block_x..block_x + width {
-    //             if depth_pass[idx].is_some() {
-    //                 self.set_pixel_unchecked(
-    //                     x,
-    //                     y,
-    //                     &(fragment_shader)(&interp[idx], &self.uniforms),
-    //                 );
-    //                 idx += 1;
-    //             }
-    //         }
-    //     }
-    // }
-
-    pub fn load_texture(&mut self, path: String) -> usize {
-        let image_bytes = std::fs::read(format!("./resources/{}", &path)).unwrap();
-        let image_data = image::load_from_memory(&image_bytes).unwrap();
-        let dimensions = image_data.dimensions();
-        let image_data = if let Some(data) = image_data.as_rgba8() {
-            data.to_vec()
-        } else {
-            image_data.into_rgba().to_vec()
-        };
-
-        self.uniforms.add_texture(Texture {
-            width: dimensions.0,
-            height: dimensions.1,
-            data: image_data.to_vec(),
-        })
+    //Shades the lanes which passed the depth and stencil tests, and only writes
+    //depth and stencil for the ones the shader and alpha test keep.
+    //Anything left to be rendered under a kept lane is covered by it.
+    pub(crate) fn shade_fragments_simd<F: FSInput, S: FragmentShader<F>>(
+        &mut self,
+        (pixel_x, pixel_y): (u32, u32),
+        interp: [Fragment<F>; 8],
+        (depths, depth_pass): (&f32x8, f32x8),
+        front_facing: bool,
+        draw_key: DrawKey,
+        shader: &S,
+    ) {
+        let depth_pass = depth_pass.move_mask();
+        let uniforms = self.uniforms.for_draw(draw_key.bindings);
+        let fragments = std::array::from_fn(|lane| {
+            (depth_pass & 1 << lane != 0).then(|| (interp[lane].input, interp[lane].context))
+        });
+        let outputs = shader
+            .fragment_shader_stamp_fn(&fragments, &uniforms)
+            .map(|output| output.filter(|output| self.passes_alpha_test(output)));
+
+        let keep = (0..8)
+            .filter(|lane| outputs[*lane].is_some())
+            .fold(0, |keep, lane| keep | 1 << lane);
+        self.write_depth_stencil_simd(pixel_x, pixel_y, depths, front_facing, keep);
+
+        let index = (pixel_x + pixel_y * self.width) as usize;
+        for (lane, output) in outputs.iter().enumerate() {
+            if let Some(output) = output {
+                self.fragment_buffer
+                    .release(index + lane, &self.fragment_slab_map);
+                self.write_fragment_output(index + lane, output, draw_key.color_state);
+            }
+        }
     }
 }
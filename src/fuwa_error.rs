@@ -0,0 +1,68 @@
+use crate::TextureFormat;
+use std::fmt;
+
+/// Everything that can go wrong when creating or changing resources.
+#[derive(Debug)]
+pub enum FuwaError {
+    /// The file couldn't be read.
+    Io(std::io::Error),
+    /// The bytes couldn't be decoded as an image.
+    Image(image::ImageError),
+    /// The format can't be used here, like loading an image as a depth format.
+    UnsupportedFormat(TextureFormat),
+    /// The data doesn't hold exactly as many texels as the given size.
+    DataSizeMismatch { expected: usize, actual: usize },
+    /// The texture would be empty, or too large to fit in memory.
+    InvalidSize,
+    /// The images don't fit together, like cubemap faces which aren't all the same
+    /// square size, or a cubemap cross which isn't 4x3 or 3x4 faces.
+    ImageSizeMismatch,
+    /// The region doesn't fit inside of the texture.
+    OutOfBounds,
+    /// The handle doesn't point to anything, or was already removed.
+    InvalidHandle,
+    /// The texture is attached to a render texture.
+    TextureInUse,
+}
+
+impl fmt::Display for FuwaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Failed to read file: {}", error),
+            Self::Image(error) => write!(f, "Failed to decode image: {}", error),
+            Self::UnsupportedFormat(format) => write!(f, "Unsupported format: {:?}", format),
+            Self::DataSizeMismatch { expected, actual } => write!(
+                f,
+                "Expected {} bytes of texture data, got {}",
+                expected, actual
+            ),
+            Self::InvalidSize => write!(f, "Texture size is zero or too large"),
+            Self::ImageSizeMismatch => write!(f, "Image sizes don't fit together"),
+            Self::OutOfBounds => write!(f, "Region is out of the texture's bounds"),
+            Self::InvalidHandle => write!(f, "Handle is invalid"),
+            Self::TextureInUse => write!(f, "Texture is attached to a render texture"),
+        }
+    }
+}
+
+impl std::error::Error for FuwaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Image(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for FuwaError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<image::ImageError> for FuwaError {
+    fn from(error: image::ImageError) -> Self {
+        Self::Image(error)
+    }
+}
@@ -1,9 +1,15 @@
 mod fuwa;
 pub use fuwa::*;
 
+mod render_target;
+pub use render_target::*;
+
 mod fuwa_stats;
 pub use fuwa_stats::*;
 
+mod fuwa_error;
+pub use fuwa_error::*;
+
 mod data;
 pub use data::*;
 
@@ -13,6 +19,11 @@ pub use textures::*;
 mod render_pipeline;
 pub use render_pipeline::*;
 
+/// The math types used throughout, like the Vec4 colors of FragmentOutput.
+pub use glam;
+/// The SIMD types used by FragmentShaderSimd.
+pub use wide;
+
 use glam::*;
 
 pub mod colors {
@@ -13,6 +13,7 @@ const HEIGHT: u32 = 720;
 
 const ROT_SPEED: f32 = 0.1;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Eq, PartialEq)]
 enum Scene {
     TexturedCube,
@@ -39,7 +40,17 @@ fn main() -> Result<(), Error> {
 
     let mut scene = Scene::TexturedCube;
 
-    let mut vertex_shader = BasicVertexShader::new();
+    let vertex_shader = BasicVertexShader::new();
+    let transform = fuwa.add_uniform_block(Transform {
+        projection: Mat4::perspective_lh(
+            std::f32::consts::FRAC_PI_2,
+            WIDTH as f32 / HEIGHT as f32,
+            0.1,
+            100.,
+        ),
+        ..Transform::default()
+    });
+    fuwa.bind_uniform_block(transform);
 
     // let pipeline = Pipeline::new(
     //     vertex_descriptor,
@@ -47,11 +58,17 @@ fn main() -> Result<(), Error> {
     //     Box::new(vertex_shader),
     // );
 
-    let box_texture_handle = fuwa.load_texture("box.png".to_string());
-    let doge_texture_handle = fuwa.load_texture("doge-bow.png".to_string());
+    fuwa.set_framebuffer_format(TextureFormat::Rgba8UnormSrgb);
+
+    let box_texture_handle = fuwa
+        .load_texture_from_path("./resources/box.png", TextureFormat::Rgba8UnormSrgb)
+        .unwrap();
+    let doge_texture_handle = fuwa
+        .load_texture_from_path("./resources/doge-bow.png", TextureFormat::Rgba8UnormSrgb)
+        .unwrap();
 
-    let mut plane_shader = Textured::new(box_texture_handle);
-    let cube_shader = ColorBlend::new();
+    let plane_shader = fuwa.add_fragment_shader(Textured::new(box_texture_handle));
+    let cube_shader = fuwa.add_fragment_shader(ColorBlend::new());
 
     let cube_data = colored_cube(1.);
     let cube_indices = cube_indices();
@@ -91,6 +108,7 @@ fn main() -> Result<(), Error> {
             }
 
             if input.key_pressed(VirtualKeyCode::T) {
+                let plane_shader = fuwa.get_fragment_shader_mut(plane_shader).unwrap();
                 if plane_shader.get_texture_handle() == box_texture_handle {
                     println!("Texture changed to Doge");
                     plane_shader.set_texture_handle(doge_texture_handle)
@@ -152,14 +170,15 @@ fn main() -> Result<(), Error> {
                     raw_vertex_list: &mut plane_data.clone(),
                 };
 
-                vertex_shader.bind_translation(offset);
-                vertex_shader.bind_rotation(rotation);
+                let transform = fuwa.get_uniform_block_mut(transform).unwrap();
+                transform.translation = offset;
+                transform.rotation = rotation;
 
-                pipeline::draw(&mut fuwa, &vertex_shader, 0, &active_cube);
-                pipeline::draw(&mut fuwa, &vertex_shader, 1, &active_plane);
+                pipeline::draw(&mut fuwa, &vertex_shader, cube_shader, &active_cube);
+                pipeline::draw(&mut fuwa, &vertex_shader, plane_shader, &active_plane);
 
-                fuwa.render(&cube_shader, 0);
-                fuwa.render(&plane_shader, 1);
+                fuwa.render(cube_shader);
+                fuwa.render(plane_shader);
 
                 if fuwa
                     .present()
@@ -168,7 +187,6 @@ fn main() -> Result<(), Error> {
                     .is_err()
                 {
                     *control_flow = ControlFlow::Exit;
-                    return;
                 };
             }
             Event::MainEventsCleared => {
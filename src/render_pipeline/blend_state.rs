@@ -0,0 +1,299 @@
+use glam::*;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstColor,
+    OneMinusDstColor,
+    DstAlpha,
+    OneMinusDstAlpha,
+    SrcAlphaSaturated,
+    ConstantColor,
+    OneMinusConstantColor,
+    ConstantAlpha,
+    OneMinusConstantAlpha,
+}
+
+impl BlendFactor {
+    fn factor(self, src: Vec4, dst: Vec4, constant: Vec4) -> Vec4 {
+        match self {
+            Self::Zero => Vec4::zero(),
+            Self::One => Vec4::one(),
+            Self::SrcColor => src,
+            Self::OneMinusSrcColor => Vec4::one() - src,
+            Self::SrcAlpha => Vec4::splat(src.w()),
+            Self::OneMinusSrcAlpha => Vec4::splat(1. - src.w()),
+            Self::DstColor => dst,
+            Self::OneMinusDstColor => Vec4::one() - dst,
+            Self::DstAlpha => Vec4::splat(dst.w()),
+            Self::OneMinusDstAlpha => Vec4::splat(1. - dst.w()),
+            Self::SrcAlphaSaturated => {
+                let f = src.w().min(1. - dst.w());
+                vec4(f, f, f, 1.)
+            }
+            Self::ConstantColor => constant,
+            Self::OneMinusConstantColor => Vec4::one() - constant,
+            Self::ConstantAlpha => Vec4::splat(constant.w()),
+            Self::OneMinusConstantAlpha => Vec4::splat(1. - constant.w()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendOperation {
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+/// Blend equation for either the color or the alpha channel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlendComponent {
+    pub src_factor: BlendFactor,
+    pub dst_factor: BlendFactor,
+    pub operation: BlendOperation,
+}
+
+impl BlendComponent {
+    pub const REPLACE: Self = Self {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::Zero,
+        operation: BlendOperation::Add,
+    };
+
+    fn apply(&self, src: Vec4, dst: Vec4, constant: Vec4) -> Vec4 {
+        let src_factor = self.src_factor.factor(src, dst, constant);
+        let dst_factor = self.dst_factor.factor(src, dst, constant);
+
+        match self.operation {
+            BlendOperation::Add => src * src_factor + dst * dst_factor,
+            BlendOperation::Subtract => src * src_factor - dst * dst_factor,
+            BlendOperation::ReverseSubtract => dst * dst_factor - src * src_factor,
+            BlendOperation::Min => src.min(dst),
+            BlendOperation::Max => src.max(dst),
+        }
+    }
+}
+
+/// How fragment shader output is combined with what is already in the framebuffer.
+/// All math is done on normalized colors.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BlendState {
+    pub color: BlendComponent,
+    pub alpha: BlendComponent,
+    pub constant: Vec4,
+}
+
+impl Default for BlendState {
+    fn default() -> Self {
+        Self::replace()
+    }
+}
+
+impl BlendState {
+    pub fn replace() -> Self {
+        Self {
+            color: BlendComponent::REPLACE,
+            alpha: BlendComponent::REPLACE,
+            constant: Vec4::zero(),
+        }
+    }
+
+    pub fn alpha_blending() -> Self {
+        Self {
+            color: BlendComponent {
+                src_factor: BlendFactor::SrcAlpha,
+                dst_factor: BlendFactor::OneMinusSrcAlpha,
+                operation: BlendOperation::Add,
+            },
+            alpha: BlendComponent {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::OneMinusSrcAlpha,
+                operation: BlendOperation::Add,
+            },
+            constant: Vec4::zero(),
+        }
+    }
+
+    pub fn premultiplied_alpha_blending() -> Self {
+        let component = BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::OneMinusSrcAlpha,
+            operation: BlendOperation::Add,
+        };
+
+        Self {
+            color: component,
+            alpha: component,
+            constant: Vec4::zero(),
+        }
+    }
+
+    pub fn blend(&self, src: Vec4, dst: Vec4) -> Vec4 {
+        let color = self.color.apply(src, dst, self.constant);
+        let alpha = self.alpha.apply(src, dst, self.constant);
+        vec4(color.x(), color.y(), color.z(), alpha.w())
+    }
+}
+
+/// Channels of the framebuffer which fragments are allowed to write to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorWriteMask {
+    pub red: bool,
+    pub green: bool,
+    pub blue: bool,
+    pub alpha: bool,
+}
+
+impl Default for ColorWriteMask {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl ColorWriteMask {
+    pub const ALL: Self = Self {
+        red: true,
+        green: true,
+        blue: true,
+        alpha: true,
+    };
+
+    pub const COLOR: Self = Self {
+        red: true,
+        green: true,
+        blue: true,
+        alpha: false,
+    };
+
+    pub const NONE: Self = Self {
+        red: false,
+        green: false,
+        blue: false,
+        alpha: false,
+    };
+
+    pub fn apply(&self, src: Vec4, dst: Vec4) -> Vec4 {
+        vec4(
+            if self.red { src.x() } else { dst.x() },
+            if self.green { src.y() } else { dst.y() },
+            if self.blue { src.z() } else { dst.z() },
+            if self.alpha { src.w() } else { dst.w() },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn src() -> Vec4 {
+        vec4(1., 0.5, 0., 0.5)
+    }
+
+    fn dst() -> Vec4 {
+        vec4(0., 0.5, 1., 0.25)
+    }
+
+    fn component(src_factor: BlendFactor, dst_factor: BlendFactor) -> BlendComponent {
+        BlendComponent {
+            src_factor,
+            dst_factor,
+            operation: BlendOperation::Add,
+        }
+    }
+
+    #[test]
+    fn replace_keeps_the_source() {
+        assert_eq!(BlendState::replace().blend(src(), dst()), src());
+    }
+
+    #[test]
+    fn alpha_blending_mixes_by_source_alpha() {
+        assert_eq!(
+            BlendState::alpha_blending().blend(src(), dst()),
+            vec4(0.5, 0.5, 0.5, 0.625)
+        );
+        let premultiplied = src() * vec4(0.5, 0.5, 0.5, 1.);
+        assert_eq!(
+            BlendState::premultiplied_alpha_blending().blend(premultiplied, dst()),
+            vec4(0.5, 0.5, 0.5, 0.625)
+        );
+    }
+
+    #[test]
+    fn color_and_alpha_use_their_own_equations() {
+        let state = BlendState {
+            color: component(BlendFactor::One, BlendFactor::One),
+            alpha: component(BlendFactor::Zero, BlendFactor::One),
+            constant: Vec4::zero(),
+        };
+        assert_eq!(state.blend(src(), dst()), vec4(1., 1., 1., 0.25));
+    }
+
+    #[test]
+    fn operations() {
+        let combine = |operation| {
+            let state = BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::One,
+                    operation,
+                },
+                ..BlendState::replace()
+            };
+            state.blend(src(), dst()).truncate()
+        };
+        assert_eq!(combine(BlendOperation::Subtract), vec3a(1., 0., -1.));
+        assert_eq!(combine(BlendOperation::ReverseSubtract), vec3a(-1., 0., 1.));
+        //Min and max ignore the factors
+        assert_eq!(combine(BlendOperation::Min), vec3a(0., 0.5, 0.));
+        assert_eq!(combine(BlendOperation::Max), vec3a(1., 0.5, 1.));
+    }
+
+    #[test]
+    fn factors() {
+        let constant = vec4(0.25, 0.5, 0.75, 0.5);
+        let factor = |factor: BlendFactor| factor.factor(src(), dst(), constant);
+        assert_eq!(
+            factor(BlendFactor::OneMinusSrcColor),
+            vec4(0., 0.5, 1., 0.5)
+        );
+        assert_eq!(factor(BlendFactor::DstAlpha), Vec4::splat(0.25));
+        assert_eq!(
+            factor(BlendFactor::OneMinusDstColor),
+            vec4(1., 0.5, 0., 0.75)
+        );
+        assert_eq!(factor(BlendFactor::ConstantColor), constant);
+        assert_eq!(factor(BlendFactor::OneMinusConstantAlpha), Vec4::splat(0.5));
+        //Limited by the room left in the destination's alpha, but never for alpha itself
+        assert_eq!(
+            factor(BlendFactor::SrcAlphaSaturated),
+            vec4(0.5, 0.5, 0.5, 1.)
+        );
+    }
+
+    #[test]
+    fn write_mask_keeps_masked_channels() {
+        assert_eq!(ColorWriteMask::ALL.apply(src(), dst()), src());
+        assert_eq!(ColorWriteMask::NONE.apply(src(), dst()), dst());
+        assert_eq!(
+            ColorWriteMask::COLOR.apply(src(), dst()),
+            vec4(1., 0.5, 0., 0.25)
+        );
+        let red_alpha = ColorWriteMask {
+            red: true,
+            green: false,
+            blue: false,
+            alpha: true,
+        };
+        assert_eq!(red_alpha.apply(src(), dst()), vec4(1., 0.5, 1., 0.5));
+    }
+}
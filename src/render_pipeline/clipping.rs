@@ -0,0 +1,276 @@
+use super::Triangle;
+use crate::FSInput;
+use glam::*;
+
+//Planes of the clip space volume -w <= x <= w, -w <= y <= w, 0 <= z <= w
+//as (a, b, c, d), where a point is inside when a*x + b*y + c*z + d*w >= 0.
+//Left, Right, Bottom, Top, Near, Far
+const CLIP_PLANES: [[f32; 4]; 6] = [
+    [1., 0., 0., 1.],
+    [-1., 0., 0., 1.],
+    [0., 1., 0., 1.],
+    [0., -1., 0., 1.],
+    [0., 0., 1., 0.],
+    [0., 0., -1., 1.],
+];
+
+pub(crate) enum ClipResult<F> {
+    Inside,
+    Outside,
+    Clipped(Vec<Triangle<F>>),
+}
+
+fn plane_distance(plane: &[f32; 4], point: &Vec4) -> f32 {
+    Vec4::from(*plane).dot(*point)
+}
+
+//Bitmask of every plane the point lies outside of
+fn outcode(point: &Vec4) -> u8 {
+    CLIP_PLANES
+        .iter()
+        .enumerate()
+        .fold(0, |code, (idx, plane)| {
+            if plane_distance(plane, point) < 0. {
+                code | 1 << idx
+            } else {
+                code
+            }
+        })
+}
+
+pub(crate) fn clip_triangle<F: FSInput>(triangle: &Triangle<F>) -> ClipResult<F> {
+    let points = triangle.get_points();
+    let codes = [
+        outcode(&points[0]),
+        outcode(&points[1]),
+        outcode(&points[2]),
+    ];
+
+    if codes[0] | codes[1] | codes[2] == 0 {
+        return ClipResult::Inside;
+    }
+
+    //Every point is outside of the same plane, so nothing is visible
+    if codes[0] & codes[1] & codes[2] != 0 {
+        return ClipResult::Outside;
+    }
+
+    let mut polygon = points
+        .iter()
+        .copied()
+        .zip(triangle.vs_input.iter().copied())
+        .zip(triangle.barycentrics.iter().copied())
+        .map(|((point, input), barycentric)| (point, input, barycentric))
+        .collect::<Vec<(Vec4, F, Vec3)>>();
+    let mut scratch = Vec::with_capacity(polygon.len() + CLIP_PLANES.len());
+
+    let crossed = codes[0] | codes[1] | codes[2];
+    for (idx, plane) in CLIP_PLANES.iter().enumerate() {
+        if crossed & 1 << idx == 0 {
+            continue;
+        }
+
+        clip_polygon(plane, &polygon, &mut scratch);
+        std::mem::swap(&mut polygon, &mut scratch);
+
+        if polygon.len() < 3 {
+            return ClipResult::Outside;
+        }
+    }
+
+    //Fan the remaining convex polygon back into triangles
+    let (p0, f0, b0) = polygon[0];
+    let triangles = polygon[1..]
+        .windows(2)
+        .map(|edge| {
+            let (p1, f1, b1) = edge[0];
+            let (p2, f2, b2) = edge[1];
+            let mut clipped = Triangle::new([p0, p1, p2], [f0, f1, f2]);
+            clipped.front_facing = triangle.front_facing;
+            clipped.barycentrics = [b0, b1, b2];
+            clipped.primitive_id = triangle.primitive_id;
+            clipped
+        })
+        .collect();
+
+    ClipResult::Clipped(triangles)
+}
+
+//Sutherland-Hodgman against a single plane
+fn clip_polygon<F: FSInput>(
+    plane: &[f32; 4],
+    input: &[(Vec4, F, Vec3)],
+    output: &mut Vec<(Vec4, F, Vec3)>,
+) {
+    output.clear();
+
+    for (idx, current) in input.iter().enumerate() {
+        let next = &input[(idx + 1) % input.len()];
+        let current_distance = plane_distance(plane, &current.0);
+        let next_distance = plane_distance(plane, &next.0);
+
+        if current_distance >= 0. {
+            output.push(*current);
+        }
+
+        if (current_distance >= 0.) != (next_distance >= 0.) {
+            let t = current_distance / (current_distance - next_distance);
+            let point = current.0 + (next.0 - current.0) * t;
+            //Linearly interpolated fields need t along the edge on screen instead
+            let screen_t = t * next.0.w() / point.w();
+            output.push((
+                point,
+                current.1 + (next.1 - current.1).scale_by_mode(t, screen_t),
+                current.2 + (next.2 - current.2) * t,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::*;
+
+    //The clip space position, and where it ends up on screen
+    //which only stays true when interpolated linearly in screen space
+    #[derive(Copy, Clone, Debug)]
+    struct Varyings {
+        position: Vec4,
+        screen: Vec2,
+    }
+
+    macro_rules! impl_ops {
+        ($($op:ident $method:ident $op_assign:ident $method_assign:ident),*) => {$(
+            impl $op for Varyings {
+                type Output = Self;
+                fn $method(self, rhs: Self) -> Self {
+                    Self {
+                        position: $op::$method(self.position, rhs.position),
+                        screen: $op::$method(self.screen, rhs.screen),
+                    }
+                }
+            }
+
+            impl $op_assign for Varyings {
+                fn $method_assign(&mut self, rhs: Self) {
+                    *self = $op::$method(*self, rhs);
+                }
+            }
+        )*};
+    }
+    impl_ops!(
+        Add add AddAssign add_assign,
+        Sub sub SubAssign sub_assign,
+        Mul mul MulAssign mul_assign,
+        Div div DivAssign div_assign
+    );
+
+    impl Mul<f32> for Varyings {
+        type Output = Self;
+        fn mul(self, rhs: f32) -> Self {
+            self.scale_by_mode(rhs, rhs)
+        }
+    }
+
+    impl MulAssign<f32> for Varyings {
+        fn mul_assign(&mut self, rhs: f32) {
+            *self = *self * rhs;
+        }
+    }
+
+    impl Div<f32> for Varyings {
+        type Output = Self;
+        fn div(self, rhs: f32) -> Self {
+            Self {
+                position: self.position / rhs,
+                screen: self.screen / rhs,
+            }
+        }
+    }
+
+    impl DivAssign<f32> for Varyings {
+        fn div_assign(&mut self, rhs: f32) {
+            *self = *self / rhs;
+        }
+    }
+
+    impl FSInput for Varyings {
+        fn scale_by_mode(self, perspective: f32, linear: f32) -> Self {
+            Self {
+                position: self.position * perspective,
+                screen: self.screen * linear,
+            }
+        }
+    }
+
+    fn varyings(point: &Vec4) -> Varyings {
+        Varyings {
+            position: *point,
+            screen: vec2(point.x(), point.y()) / point.w(),
+        }
+    }
+
+    fn triangle(points: [Vec4; 3]) -> Triangle<Varyings> {
+        Triangle::new(
+            points,
+            [
+                varyings(&points[0]),
+                varyings(&points[1]),
+                varyings(&points[2]),
+            ],
+        )
+    }
+
+    #[test]
+    fn inside_triangles_are_kept() {
+        let triangle = triangle([
+            vec4(-0.5, -0.5, 0.5, 1.),
+            vec4(0.5, -0.5, 0.5, 1.),
+            vec4(0., 0.5, 0.5, 1.),
+        ]);
+        assert!(matches!(clip_triangle(&triangle), ClipResult::Inside));
+    }
+
+    #[test]
+    fn triangles_past_one_plane_are_culled() {
+        let triangle = triangle([
+            vec4(2., -0.5, 0.5, 1.),
+            vec4(3., -0.5, 0.5, 1.),
+            vec4(2.5, 0.5, 0.5, 1.),
+        ]);
+        assert!(matches!(clip_triangle(&triangle), ClipResult::Outside));
+    }
+
+    #[test]
+    fn clipping_the_near_plane_splits_into_a_quad() {
+        //The first point is behind the camera, so the near plane cuts off a corner,
+        //and the others are at different distances so w varies along the cut edges
+        let points = [
+            vec4(0., 0., -1., 1.),
+            vec4(1., 0., 1.5, 2.),
+            vec4(0., 2., 3.5, 4.),
+        ];
+        let triangles = match clip_triangle(&triangle(points)) {
+            ClipResult::Clipped(triangles) => triangles,
+            _ => panic!("Expected the triangle to be clipped"),
+        };
+
+        assert_eq!(triangles.len(), 2);
+        for clipped in &triangles {
+            let vertices = clipped.points.iter().zip(&clipped.vs_input);
+            for (point, barycentric) in clipped.points.iter().zip(&clipped.barycentrics) {
+                let weighted = points[0] * barycentric.x()
+                    + points[1] * barycentric.y()
+                    + points[2] * barycentric.z();
+                assert!((weighted - *point).abs().max_element() < 1e-5);
+            }
+            for (point, input) in vertices {
+                assert_eq!(outcode(point), 0);
+                let expected = varyings(point);
+                assert!((input.position - expected.position).abs().max_element() < 1e-5);
+                assert!((input.screen - expected.screen).abs().max_element() < 1e-5);
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use wide::f32x8;
+
+/// Comparison used by depth and stencil tests, where a fragment passes when
+/// `new_value <op> stored_value` holds.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompareFunction {
+    Never,
+    Less,
+    LessEqual,
+    Equal,
+    Greater,
+    GreaterEqual,
+    NotEqual,
+    Always,
+}
+
+impl CompareFunction {
+    pub fn compare<T: PartialOrd>(self, value: T, reference: T) -> bool {
+        match self {
+            Self::Never => false,
+            Self::Less => value < reference,
+            Self::LessEqual => value <= reference,
+            Self::Equal => value == reference,
+            Self::Greater => value > reference,
+            Self::GreaterEqual => value >= reference,
+            Self::NotEqual => value != reference,
+            Self::Always => true,
+        }
+    }
+
+    pub fn compare_simd(self, values: f32x8, reference: f32x8) -> f32x8 {
+        match self {
+            Self::Never => f32x8::ZERO,
+            Self::Less => values.cmp_lt(reference),
+            Self::LessEqual => values.cmp_le(reference),
+            Self::Equal => values.cmp_eq(reference),
+            //wide's cmp_gt tests >= on the upper four lanes without AVX
+            Self::Greater => reference.cmp_lt(values),
+            Self::GreaterEqual => values.cmp_ge(reference),
+            Self::NotEqual => values.cmp_ne(reference),
+            Self::Always => !f32x8::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [CompareFunction; 8] = [
+        CompareFunction::Never,
+        CompareFunction::Less,
+        CompareFunction::LessEqual,
+        CompareFunction::Equal,
+        CompareFunction::Greater,
+        CompareFunction::GreaterEqual,
+        CompareFunction::NotEqual,
+        CompareFunction::Always,
+    ];
+
+    #[test]
+    fn compare_matches_the_operator() {
+        //Results for a value below, equal to and above the reference
+        let expected = [
+            [false, false, false],
+            [true, false, false],
+            [true, true, false],
+            [false, true, false],
+            [false, false, true],
+            [false, true, true],
+            [true, false, true],
+            [true, true, true],
+        ];
+        for (function, expected) in ALL.iter().zip(expected.iter()) {
+            let results = [
+                function.compare(0.25, 0.5),
+                function.compare(0.5, 0.5),
+                function.compare(0.75, 0.5),
+            ];
+            assert_eq!(&results, expected, "{:?}", function);
+        }
+    }
+
+    #[test]
+    fn compare_simd_matches_compare() {
+        let values = [0., 0.25, 0.5, 0.75, 1., 0.5, -1., 2.];
+        for function in ALL.iter() {
+            let mask = function
+                .compare_simd(f32x8::from(values), f32x8::splat(0.5))
+                .move_mask();
+            for (lane, &value) in values.iter().enumerate() {
+                assert_eq!(
+                    mask & 1 << lane != 0,
+                    function.compare(value, 0.5),
+                    "{:?} lane {}",
+                    function,
+                    lane
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,17 @@
+/// Which triangles are thrown away before rasterization, based on their facing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+}
+
+impl CullMode {
+    pub fn culls(self, backfacing: bool) -> bool {
+        match self {
+            Self::None => false,
+            Self::Front => !backfacing,
+            Self::Back => backfacing,
+        }
+    }
+}
@@ -1,26 +1,52 @@
+use super::CompareFunction;
 use bytemuck::cast;
 //use rayon::prelude::*;
 use wide::f32x8;
 
+//Extra room at the end, so 8 wide stamps on the last row stay in bounds
+const STAMP_PADDING: usize = 8;
+
+//Depth is stored as z / w, so the far plane sits at 1
+const DEPTH_CLEAR: f32 = 1.;
+
+/// How fragments are tested against, and written to, the depth buffer.
+/// When the test is disabled every fragment passes and depth is never written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DepthState {
+    pub test_enabled: bool,
+    pub write_enabled: bool,
+    pub compare: CompareFunction,
+}
+
+impl Default for DepthState {
+    fn default() -> Self {
+        Self {
+            test_enabled: true,
+            write_enabled: true,
+            compare: CompareFunction::Less,
+        }
+    }
+}
+
 pub(crate) struct DepthBuffer {
     pub(crate) depth_buffer: Vec<f32>,
-    //TODO: Implement depth functions like GREATER THAN or LESS THAN
+    pub(crate) clear_value: f32,
 }
 
 impl DepthBuffer {
     pub(crate) fn new(width: u32, height: u32) -> Self {
         Self {
-            depth_buffer: vec![f32::NEG_INFINITY; (width * height) as usize],
+            depth_buffer: vec![DEPTH_CLEAR; (width * height) as usize + STAMP_PADDING],
+            clear_value: DEPTH_CLEAR,
         }
     }
 
     pub(crate) fn clear(&mut self) {
-        //TODO: Is this safe/faster than parallel?
-        unsafe {
-            let target = self.depth_buffer.as_mut_ptr();
-            let len = self.depth_buffer.len();
-            std::ptr::write_bytes(target, 0, len)
-        }
+        //TODO: Is this faster than parallel?
+        let clear_value = self.clear_value;
+        self.depth_buffer
+            .iter_mut()
+            .for_each(|depth| *depth = clear_value);
         //let step = self.depth_buffer.len() / self.thread_count;
         //self.depth_buffer
         // .par_chunks_mut(step)
@@ -36,13 +62,18 @@ impl DepthBuffer {
         // });
     }
 
-    pub fn try_set_depth(&mut self, index: usize, depth: f32) -> bool {
+    pub fn try_set_depth(&mut self, index: usize, depth: f32, state: &DepthState) -> bool {
         //optick::event!();
+        if !state.test_enabled {
+            return true;
+        }
 
         unsafe {
             let prev = self.depth_buffer.get_unchecked_mut(index);
-            if depth > *prev {
-                *prev = depth;
+            if state.compare.compare(depth, *prev) {
+                if state.write_enabled {
+                    *prev = depth;
+                }
                 true
             } else {
                 false
@@ -50,21 +81,34 @@ impl DepthBuffer {
         }
     }
 
-    pub fn try_set_depth_simd(&mut self, index: usize, depths: &f32x8) -> Option<f32x8> {
+    //Lanes holding NaN are treated as uncovered, and always fail
+    pub fn try_set_depth_simd(
+        &mut self,
+        index: usize,
+        depths: &f32x8,
+        state: &DepthState,
+    ) -> Option<f32x8> {
+        let covered = depths.cmp_eq(*depths);
+        if !state.test_enabled {
+            return if covered.any() { Some(covered) } else { None };
+        }
+
         unsafe {
             let prev = f32x8::from(self.depth_buffer.get_unchecked(index..index + 8));
-            let depth_pass_mask = depths.cmp_gt(prev);
+            let depth_pass_mask = state.compare.compare_simd(*depths, prev) & covered;
 
             if depth_pass_mask.any() {
-                self.depth_buffer
-                    .get_unchecked_mut(index..index + 8)
-                    .copy_from_slice(&cast::<_, [f32; 8]>(depths.max(prev)));
+                if state.write_enabled {
+                    self.depth_buffer
+                        .get_unchecked_mut(index..index + 8)
+                        .copy_from_slice(&cast::<_, [f32; 8]>(
+                            depth_pass_mask.blend(*depths, prev),
+                        ));
+                }
                 Some(depth_pass_mask)
             } else {
                 None
             }
         }
     }
-
-    //TODO: Move Try_SET_DEPTH_BLOCK to here
 }
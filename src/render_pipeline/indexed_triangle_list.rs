@@ -2,3 +2,25 @@ pub struct IndexedVertexList<'a, V: Send + Sync> {
     pub raw_vertex_list: &'a [V],
     pub index_list: &'a [usize],
 }
+
+/// Owned counterpart of IndexedVertexList, which Fuwa holds on to so it can be drawn by handle.
+pub struct IndexedVertexBuffer<V: Send + Sync> {
+    pub raw_vertex_list: Vec<V>,
+    pub index_list: Vec<usize>,
+}
+
+impl<V: Send + Sync> IndexedVertexBuffer<V> {
+    pub fn new(raw_vertex_list: Vec<V>, index_list: Vec<usize>) -> Self {
+        Self {
+            raw_vertex_list,
+            index_list,
+        }
+    }
+
+    pub fn as_list(&self) -> IndexedVertexList<'_, V> {
+        IndexedVertexList {
+            raw_vertex_list: &self.raw_vertex_list,
+            index_list: &self.index_list,
+        }
+    }
+}
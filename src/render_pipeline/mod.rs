@@ -7,10 +7,28 @@ pub use pipeline::*;
 mod triangle;
 pub use triangle::*;
 
+mod cull_mode;
+pub use cull_mode::*;
+
+mod clipping;
+pub(crate) use clipping::*;
+
+mod viewport;
+pub use viewport::*;
+
 pub mod rasterization;
 
 mod shaders;
 pub use shaders::*;
 
+mod compare_function;
+pub use compare_function::*;
+
 mod depth_buffer;
-pub(crate) use depth_buffer::*;
+pub use depth_buffer::*;
+
+mod stencil_buffer;
+pub use stencil_buffer::*;
+
+mod blend_state;
+pub use blend_state::*;
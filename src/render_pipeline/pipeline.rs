@@ -1,8 +1,8 @@
-use super::Triangle;
-use crate::{rasterization::rasterizer, rasterization::SlabPtr, Fuwa, IndexedVertexList};
-use crate::{FSInput, VSInput, VertexShader};
+use super::{clip_triangle, ClipResult, Triangle};
+use crate::rasterization::{rasterizer, DrawKey, FragmentSink, SlabPtr};
+use crate::{FSInput, FragmentShader, Fuwa, Handle, IndexedVertexBuffer, IndexedVertexList};
+use crate::{RenderTarget, VSInput, VertexShader};
 use glam::*;
-use raw_window_handle::HasRawWindowHandle;
 use rayon::prelude::*;
 
 // pub struct Pipeline<V: VSInput, F: FSInput> {
@@ -24,37 +24,92 @@ use rayon::prelude::*;
 //     PipelinePtr(self as *const Self)
 // }
 
-pub fn draw<V: VSInput, F: FSInput, W: HasRawWindowHandle + Send + Sync>(
+/// Rasterizes the triangles, leaving their fragments to be shaded by
+/// rendering the fragment shader with the same handle, unless it can discard
+/// or the draw needs what's already in the framebuffer.
+pub fn draw<V: VSInput, F: FSInput, S: FragmentShader<F> + 'static, T: RenderTarget>(
     // &'fs self,
-    fuwa: &mut Fuwa<W>,
+    fuwa: &mut Fuwa<T>,
     vertex_shader: &impl VertexShader<V, F>,
-    fs_index: usize,
+    fragment_shader: Handle<S>,
     indexed_list: &IndexedVertexList<V>,
 ) {
+    //Shaders which can discard get run as they're drawn, so they need a copy
+    //of their own while Fuwa is borrowed for rasterization
+    let shader = fuwa
+        .get_fragment_shader(fragment_shader)
+        .expect("Invalid fragment shader handle!");
+    let immediate = S::CAN_DISCARD || fuwa.alpha_test.is_some() || fuwa.reads_framebuffer();
+    let shader = immediate.then(|| shader.clone());
+    let draw_key = DrawKey {
+        shader: fragment_shader.cast(),
+        bindings: fuwa.uniforms.snapshot_bindings(),
+        color_state: fuwa.snapshot_color_state(),
+    };
+    fuwa.mark_render_texture_drawn();
+
     //optick::next_frame();
+    let uniforms = fuwa.uniforms.bound();
     let vs_output = indexed_list
         .raw_vertex_list
         .into_par_iter()
-        .map(|vertex| vertex_shader.vertex_shader_fn(vertex))
-        .collect::<Vec<(Vec3A, F)>>();
+        .map(|vertex| vertex_shader.vertex_shader_fn(vertex, &uniforms))
+        .collect::<Vec<(Vec4, F)>>();
+
+    let sink = match &shader {
+        Some(shader) => FragmentSink::Immediate(shader),
+        None => {
+            fuwa.fragment_buffer.mark_pending();
+            FragmentSink::Deferred(SlabPtr(fuwa.fragment_slab_map.get_mut_slab::<F>()))
+        }
+    };
+    assemble_triangles(fuwa, vs_output, draw_key, sink, indexed_list.index_list);
+
+    //Immediate draws are done with their snapshots already
+    fuwa.reset_draw_states();
+}
 
-    assemble_triangles(fuwa, vs_output, fs_index, &indexed_list.index_list)
+/// Draws a vertex buffer held by Fuwa.
+pub fn draw_buffer<
+    V: VSInput + 'static,
+    F: FSInput,
+    S: FragmentShader<F> + 'static,
+    T: RenderTarget,
+>(
+    fuwa: &mut Fuwa<T>,
+    vertex_shader: &impl VertexShader<V, F>,
+    fragment_shader: Handle<S>,
+    vertex_buffer: Handle<IndexedVertexBuffer<V>>,
+) {
+    fuwa.get_vertex_buffer(vertex_buffer)
+        .expect("Invalid vertex buffer handle!");
+
+    //Taken out of its slot while drawing, so Fuwa can be borrowed mutably
+    let slot = &mut fuwa.vertex_buffers[vertex_buffer.cast()];
+    let buffer = std::mem::replace(slot, Box::new(()));
+    let list = buffer
+        .downcast_ref::<IndexedVertexBuffer<V>>()
+        .unwrap()
+        .as_list();
+    draw(fuwa, vertex_shader, fragment_shader, &list);
+    fuwa.vertex_buffers[vertex_buffer.cast()] = buffer;
 }
 
-// fn run_vertex_shader(vertex_list: &[V]) -> Vec<(Vec3A, F)> {
+// fn run_vertex_shader(vertex_list: &[V]) -> Vec<(Vec4, F)> {
 //     //transform all incoming verts,
 //     //and prepares them for assembly
 //     vertex_list
 //         .into_par_iter()
 //         .map(|vertex| self.vertex_shader.vertex_shader_fn(vertex))
-//         .collect::<Vec<(Vec3A, F)>>()
+//         .collect::<Vec<(Vec4, F)>>()
 // }
 
-fn assemble_triangles<F: FSInput, W: HasRawWindowHandle + Sync + Send>(
+fn assemble_triangles<F: FSInput, S: FragmentShader<F>, T: RenderTarget>(
     //&'fs self,
-    fuwa: &mut Fuwa<W>,
-    vs_output: Vec<(Vec3A, F)>,
-    fs_index: usize,
+    fuwa: &mut Fuwa<T>,
+    vs_output: Vec<(Vec4, F)>,
+    draw_key: DrawKey,
+    sink: FragmentSink<F, S>,
     index_list: &[usize],
 ) {
     //loop through and build triangles,
@@ -62,47 +117,75 @@ fn assemble_triangles<F: FSInput, W: HasRawWindowHandle + Sync + Send>(
 
     //let self_ptr = self.get_self_ptr();
     let fuwa_ptr = fuwa.get_self_ptr();
-    let slab_ptr = SlabPtr(fuwa.fragment_slab_map.get_mut_slab::<F>());
+    let cull_mode = fuwa.cull_mode;
 
-    index_list.par_chunks_exact(3).for_each(|indices| unsafe {
+    let assemble = |(primitive_id, indices): (usize, &[usize])| unsafe {
         let idx0 = indices[0];
         let idx1 = indices[1];
         let idx2 = indices[2];
 
+        //The first vertex is the provoking one, before clipping can replace it
+        let provoking = vs_output[idx0].1;
+        let mut vs_input = [provoking, vs_output[idx1].1, vs_output[idx2].1];
+        vs_input[1].copy_flat(&provoking);
+        vs_input[2].copy_flat(&provoking);
+
         let mut triangle = Triangle::new(
             [vs_output[idx0].0, vs_output[idx1].0, vs_output[idx2].0],
-            [vs_output[idx0].1, vs_output[idx1].1, vs_output[idx2].1],
+            vs_input,
         );
+        triangle.primitive_id = primitive_id as u32;
 
-        if !triangle.is_backfacing() {
-            process_triangle(&mut *fuwa_ptr.0, &mut triangle, fs_index, slab_ptr)
+        let backfacing = triangle.is_backfacing();
+        if !cull_mode.culls(backfacing) {
+            if backfacing {
+                triangle.flip_winding();
+            }
+            process_triangle(&mut *fuwa_ptr.0, &mut triangle, draw_key, sink)
         }
-    });
+    };
+
+    //Immediately shaded fragments test depth before shading and only write it after,
+    //so overlapping triangles have to be drawn one at a time, in the order given
+    match sink {
+        FragmentSink::Deferred(_) => index_list
+            .par_chunks_exact(3)
+            .enumerate()
+            .for_each(assemble),
+        FragmentSink::Immediate(_) => index_list.chunks_exact(3).enumerate().for_each(assemble),
+    }
 }
 
-fn process_triangle<F: FSInput, W: HasRawWindowHandle + Sync + Send>(
+fn process_triangle<F: FSInput, S: FragmentShader<F>, T: RenderTarget>(
     //&'fs self,
-    fuwa: &mut Fuwa<W>,
+    fuwa: &mut Fuwa<T>,
     triangle: &mut Triangle<F>,
-    fs_index: usize,
-    slab_ptr: SlabPtr<F>,
+    draw_key: DrawKey,
+    sink: FragmentSink<F, S>,
 ) {
-    //Do something later
-    post_process_triangle(fuwa, triangle, fs_index, slab_ptr);
+    //Clip against the view frustum, so nothing behind or crossing
+    //the camera ever gets divided by w
+    match clip_triangle(triangle) {
+        ClipResult::Inside => post_process_triangle(fuwa, triangle, draw_key, sink),
+        ClipResult::Outside => (),
+        ClipResult::Clipped(triangles) => triangles
+            .into_iter()
+            .for_each(|mut triangle| post_process_triangle(fuwa, &mut triangle, draw_key, sink)),
+    }
 }
 
-fn post_process_triangle<F: FSInput, W: HasRawWindowHandle + Sync + Send>(
+fn post_process_triangle<F: FSInput, S: FragmentShader<F>, T: RenderTarget>(
     //&'fs self,
-    fuwa: &mut Fuwa<W>,
+    fuwa: &mut Fuwa<T>,
     triangle: &mut Triangle<F>,
-    fs_index: usize,
-    slab_ptr: SlabPtr<F>,
+    draw_key: DrawKey,
+    sink: FragmentSink<F, S>,
 ) {
     //Transform triangle to screen space
     triangle.transform_screen_space_perspective(fuwa);
 
     //Draw the triangle
-    rasterizer::triangle(fuwa.get_self_ptr(), triangle, fs_index, slab_ptr);
+    rasterizer::triangle(fuwa.get_self_ptr(), triangle, draw_key, sink);
 }
 //}
 
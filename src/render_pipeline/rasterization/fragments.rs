@@ -1,31 +1,99 @@
-use crate::FSInput;
+use crate::{AnyResource, FSInput, FragmentContext, Handle};
 use sharded_slab::Slab;
 use type_map::TypeMap;
 
 pub(crate) struct FragmentBuffer {
     fragments: Vec<Option<FragmentKey>>,
+    //Whether any fragment might still be waiting to be shaded
+    pending: bool,
 }
 
 impl FragmentBuffer {
     pub(crate) fn new(width: u32, height: u32) -> Self {
         Self {
             fragments: vec![None; (width * height) as usize],
+            pending: false,
         }
     }
 
-    pub(crate) fn set_fragment(&mut self, index: usize, fragment: FragmentKey) {
+    //Set before deferred draws, rather than per fragment while rasterizing in parallel
+    pub(crate) fn mark_pending(&mut self) {
+        self.pending = true;
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        self.pending
+    }
+
+    //Checks whether anything is actually left after rendering or releasing fragments
+    pub(crate) fn refresh_pending(&mut self) {
+        if self.pending {
+            self.pending = self.fragments.iter().any(Option::is_some);
+        }
+    }
+
+    //Any fragment which hasn't been rendered yet gets replaced, and dropped from its slab
+    pub(crate) fn set_fragment(
+        &mut self,
+        index: usize,
+        fragment: FragmentKey,
+        slab_map: &FragmentSlabMap,
+    ) {
+        self.release(index, slab_map);
         self.fragments[index] = Some(fragment)
     }
 
+    pub(crate) fn release(&mut self, index: usize, slab_map: &FragmentSlabMap) {
+        if let Some(prev) = self.fragments[index].take() {
+            (prev.remove)(slab_map, prev.fragment_key);
+        }
+    }
+
+    //Drops every fragment waiting to be shaded with the shader
+    pub(crate) fn release_shader(
+        &mut self,
+        shader: Handle<AnyResource>,
+        slab_map: &FragmentSlabMap,
+    ) {
+        for index in 0..self.fragments.len() {
+            let drawn_with_shader = self.fragments[index]
+                .as_ref()
+                .is_some_and(|fragment| fragment.draw_key.shader == shader);
+            if drawn_with_shader {
+                self.release(index, slab_map);
+            }
+        }
+        self.refresh_pending();
+    }
+
     pub(crate) fn get_fragments_view_mut(&mut self) -> &mut [Option<FragmentKey>] {
         &mut self.fragments
     }
 }
 
+//Identifies what a draw's fragments get shaded with
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) struct DrawKey {
+    pub(crate) shader: Handle<AnyResource>,
+    //Index of the uniform bindings the draw was made with
+    pub(crate) bindings: u32,
+    //Index of the blend state and color write mask the draw was made with
+    pub(crate) color_state: u32,
+}
+
 #[derive(Clone)]
 pub(crate) struct FragmentKey {
-    pub(crate) shader_index: usize,
+    pub(crate) draw_key: DrawKey,
     pub(crate) fragment_key: usize,
+    //Removes the fragment from the slab of its input type
+    pub(crate) remove: fn(&FragmentSlabMap, usize),
+}
+
+//What gets stored per fragment until it's shaded
+#[derive(Copy, Clone)]
+pub struct Fragment<F> {
+    pub(crate) input: F,
+    pub(crate) context: FragmentContext<F>,
 }
 
 pub struct FragmentSlabMap {
@@ -39,40 +107,55 @@ impl FragmentSlabMap {
         }
     }
 
-    pub fn get_mut_slab<F: FSInput + 'static>(&mut self) -> &mut Slab<F> {
-        if !self.slab_map.contains::<Slab<F>>() {
-            let slab: Slab<F> = Slab::new_with_config();
+    pub fn get_mut_slab<F: FSInput + 'static>(&mut self) -> &mut Slab<Fragment<F>> {
+        if !self.slab_map.contains::<Slab<Fragment<F>>>() {
+            let slab: Slab<Fragment<F>> = Slab::new_with_config();
             self.slab_map.insert(slab);
         }
 
-        self.slab_map.get_mut::<Slab<F>>().unwrap()
+        self.slab_map.get_mut::<Slab<Fragment<F>>>().unwrap()
     }
 
-    pub(crate) fn remove_slab<F: FSInput + 'static>(&mut self) -> Option<Slab<F>> {
-        self.slab_map.remove::<Slab<F>>()
+    pub(crate) fn get_slab<F: FSInput + 'static>(&self) -> Option<&Slab<Fragment<F>>> {
+        self.slab_map.get::<Slab<Fragment<F>>>()
     }
-    pub(crate) fn insert_slab<F: FSInput + 'static>(&mut self, slab: Slab<F>) {
-        self.slab_map.insert(slab);
+}
+
+fn remove_fragment<F: FSInput>(slab_map: &FragmentSlabMap, fragment_key: usize) {
+    if let Some(slab) = slab_map.get_slab::<F>() {
+        slab.remove(fragment_key);
     }
 }
 
 unsafe impl<F> Send for SlabPtr<F> {}
 unsafe impl<F> Sync for SlabPtr<F> {}
 #[derive(Copy, Clone)]
-pub(crate) struct SlabPtr<F>(pub(crate) *mut Slab<F>);
+pub(crate) struct SlabPtr<F>(pub(crate) *mut Slab<Fragment<F>>);
 
-impl<F> SlabPtr<F> {
-    pub(crate) fn new(slab: &mut Slab<F>) -> Self {
-        Self(slab as *mut Slab<F>)
-    }
-
-    pub(crate) fn insert_fragment(&self, shader_index: usize, input: F) -> FragmentKey {
+impl<F: FSInput> SlabPtr<F> {
+    pub(crate) fn insert_fragment(&self, draw_key: DrawKey, input: Fragment<F>) -> FragmentKey {
         unsafe {
             let fragment_key = (*self.0).insert(input).unwrap();
             FragmentKey {
-                shader_index,
+                draw_key,
                 fragment_key,
+                remove: remove_fragment::<F>,
             }
         }
     }
 }
+
+//Where a draw's fragments go once they pass the depth and stencil tests
+pub(crate) enum FragmentSink<'a, F, S> {
+    //Stored until the shader gets rendered
+    Deferred(SlabPtr<F>),
+    //Shaded right away, so discarded fragments never write depth or stencil
+    Immediate(&'a S),
+}
+
+impl<F: FSInput, S> Clone for FragmentSink<'_, F, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<F: FSInput, S> Copy for FragmentSink<'_, F, S> {}
@@ -1,6 +1,5 @@
-use crate::Fuwa;
+use crate::{Fuwa, RenderTarget};
 use glam::*;
-use raw_window_handle::HasRawWindowHandle;
 
 pub struct RasterBoundingBox([f32; 4]);
 
@@ -31,19 +30,17 @@ impl RasterBoundingBox {
     }
 }
 
-impl<W: HasRawWindowHandle> Fuwa<W> {
+impl<T: RenderTarget> Fuwa<T> {
     pub fn calculate_raster_bb(&self, points: &[Vec2; 3]) -> RasterBoundingBox {
-        let zero = Vec3A::zero();
-        let width_vec = Vec3A::splat(self.width as f32);
-        let height_vec = Vec3A::splat(self.height as f32);
+        let [bounds_min_x, bounds_min_y, bounds_max_x, bounds_max_y] = self.get_raster_bounds();
         let x_vec = vec3a(points[0].x(), points[1].x(), points[2].x());
         let y_vec = vec3a(points[0].y(), points[1].y(), points[2].y());
 
-        let mut min_x = x_vec.max(zero).min_element();
-        let mut max_x = x_vec.min(width_vec).max_element();
+        let mut min_x = x_vec.max(Vec3A::splat(bounds_min_x)).min_element();
+        let mut max_x = x_vec.min(Vec3A::splat(bounds_max_x)).max_element();
 
-        let mut min_y = y_vec.max(zero).min_element();
-        let mut max_y = y_vec.min(height_vec).max_element();
+        let mut min_y = y_vec.max(Vec3A::splat(bounds_min_y)).min_element();
+        let mut max_y = y_vec.min(Vec3A::splat(bounds_max_y)).max_element();
 
         min_x = min_x.floor();
         min_y = min_y.floor();
@@ -52,4 +49,25 @@ impl<W: HasRawWindowHandle> Fuwa<W> {
 
         RasterBoundingBox([min_x, min_y, max_x, max_y])
     }
+
+    //The area pixels can be drawn in, the framebuffer clipped
+    //by the viewport and scissor rectangles
+    fn get_raster_bounds(&self) -> [f32; 4] {
+        let viewport = &self.viewport;
+        let mut bounds = [
+            viewport.x.max(0.),
+            viewport.y.max(0.),
+            (viewport.x + viewport.width).min(self.width as f32),
+            (viewport.y + viewport.height).min(self.height as f32),
+        ];
+
+        if let Some(scissor) = &self.scissor {
+            bounds[0] = bounds[0].max(scissor.x as f32);
+            bounds[1] = bounds[1].max(scissor.y as f32);
+            bounds[2] = bounds[2].min(scissor.x.saturating_add(scissor.width) as f32);
+            bounds[3] = bounds[3].min(scissor.y.saturating_add(scissor.height) as f32);
+        }
+
+        bounds
+    }
 }
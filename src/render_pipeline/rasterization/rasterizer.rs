@@ -1,10 +1,9 @@
-use super::{RasterBoundingBox, SlabPtr};
-use crate::FSInput;
-use crate::{FuwaPtr, Triangle};
+use super::{DrawKey, Fragment, FragmentSink, RasterBoundingBox};
+use crate::{FSInput, FragmentContext, FragmentDerivatives, FragmentShader};
+use crate::{FuwaPtr, RenderTarget, Triangle};
 use bytemuck::cast;
 use glam::*;
 use lazy_static::lazy_static;
-use raw_window_handle::HasRawWindowHandle;
 use rayon::prelude::*;
 use wide::{f32x4, f32x8};
 
@@ -19,27 +18,27 @@ const INNER_STAMP_HEIGHT: u32 = 1;
 const OUTER_BLOCK_WIDTH: u32 = 16;
 const OUTER_BLOCK_HEIGHT: u32 = 16;
 
-pub(crate) fn triangle<F: FSInput, W: HasRawWindowHandle + Send + Sync>(
-    fuwa: FuwaPtr<W>,
+pub(crate) fn triangle<F: FSInput, S: FragmentShader<F>, T: RenderTarget>(
+    fuwa: FuwaPtr<T>,
     triangle: &Triangle<F>,
-    fs_index: usize,
-    slab_ptr: SlabPtr<F>,
+    draw_key: DrawKey,
+    sink: FragmentSink<F, S>,
 ) {
     let points2d = triangle.get_points_as_vec2();
     let bb = unsafe { (*fuwa.0).calculate_raster_bb(&points2d) };
 
-    rasterize_triangle_blocks(fuwa, triangle, fs_index, bb, slab_ptr)
+    rasterize_triangle_blocks(fuwa, triangle, draw_key, bb, sink)
 }
 
-fn rasterize_triangle_blocks<F: FSInput, W: HasRawWindowHandle + Send + Sync>(
-    fuwa: FuwaPtr<W>,
+fn rasterize_triangle_blocks<F: FSInput, S: FragmentShader<F>, T: RenderTarget>(
+    fuwa: FuwaPtr<T>,
     triangle: &Triangle<F>,
-    fs_index: usize,
+    draw_key: DrawKey,
     bb: RasterBoundingBox,
-    slab_ptr: SlabPtr<F>,
+    sink: FragmentSink<F, S>,
 ) {
     //optick::event!();
-    let points = triangle.get_points_as_vec3a();
+    let points = triangle.get_points();
 
     //Deltas - Number indicates the edge
     let dx01 = points[0].x() - points[1].x();
@@ -54,6 +53,13 @@ fn rasterize_triangle_blocks<F: FSInput, W: HasRawWindowHandle + Send + Sync>(
     let c1 = dy20 * points[2].x() - dx20 * points[2].y();
     let c2 = dy01 * points[0].x() - dx01 * points[0].y();
 
+    let gradients = InterpolantGradients::new(triangle, c0 + c1 + c2, (dx20, dy20), (dx01, dy01));
+    let top_left = [
+        is_top_left(dx12, dy12),
+        is_top_left(dx20, dy20),
+        is_top_left(dx01, dy01),
+    ];
+
     let [min_x, min_y, max_x, max_y] = bb.prepare();
     //Start traversing inner blocks
     //This can be done in parallel
@@ -101,54 +107,10 @@ fn rasterize_triangle_blocks<F: FSInput, W: HasRawWindowHandle + Send + Sync>(
                         }
                     }
 
-                    //TODO: SIMD-ify this block next
-                    //Currently it's faster to just ignore this early out
-                    //We can draw this block in one go
-                    // (Inside, Inside, Inside) => {
-                    //     row_already_draw = true;
-
-                    //     let a00 = cast::<_, [f32; 4]>(a_set)[0];
-                    //     let b00 = cast::<_, [f32; 4]>(b_set)[0];
-                    //     let c00 = cast::<_, [f32; 4]>(c_set)[0];
-
-                    //     let depths = get_interpolated_z_block(
-                    //         triangle,
-                    //         (a00, b00, c00),
-                    //         (dx12, dx20, dx01),
-                    //         (dy12, dy20, dy01),
-                    //         OUTER_BLOCK_WIDTH,
-                    //         OUTER_BLOCK_HEIGHT,
-                    //     );
-
-                    //     unsafe {
-                    //         if let Some(depth_pass) = (*fuwa.0).try_set_depth_block(
-                    //             (block_x0, block_y0),
-                    //             (OUTER_BLOCK_WIDTH, OUTER_BLOCK_HEIGHT),
-                    //             depths,
-                    //         ) {
-                    //             let interpolated_verts = get_interpolated_triangle_block(
-                    //                 triangle,
-                    //                 (a00, b00, c00),
-                    //                 (dx12, dx20, dx01),
-                    //                 (dy12, dy20, dy01),
-                    //                 (OUTER_BLOCK_WIDTH, OUTER_BLOCK_HEIGHT),
-                    //                 &depth_pass,
-                    //             );
-                    //             (*fuwa.0).set_fragments_block(
-                    //                 (block_x0, block_y0),
-                    //                 (OUTER_BLOCK_WIDTH, OUTER_BLOCK_HEIGHT),
-                    //                 &depth_pass,
-                    //                 interpolated_verts,
-                    //                 fs_index,
-                    //                 slab_ptr,
-                    //             )
-                    //         }
-                    //     }
-                    // }
+                    //Fully and partially covered blocks alike get drawn
+                    //pixel-by-pixel, as the stamps are already 8 wide
                     _ => {
                         row_already_draw = true;
-                        //We have a partially covered block, so we
-                        //have to draw the block pixel-by-pixel
                         //These are constants for our new starting point at bx0, by0
                         //and were calculated previously
                         let mut cy0 = c0 + (dx12 * (block_y0 as f32 + *STAMP_OFFSET_Y))
@@ -166,7 +128,10 @@ fn rasterize_triangle_blocks<F: FSInput, W: HasRawWindowHandle + Send + Sync>(
                         let one_step_y1 = f32x8::splat(dx20 * INNER_STAMP_HEIGHT as f32);
                         let one_step_y2 = f32x8::splat(dx01 * INNER_STAMP_HEIGHT as f32);
 
-                        (block_y0..block_y1)
+                        //Keep the last blocks from running past the edge of the bounding box
+                        let max_x_vec = f32x8::splat(max_x as f32);
+
+                        (block_y0..block_y1.min(max_y))
                             .step_by(INNER_STAMP_HEIGHT as usize)
                             .for_each(|pixel_y| {
                                 //Reset values for horizontal traversal
@@ -177,31 +142,59 @@ fn rasterize_triangle_blocks<F: FSInput, W: HasRawWindowHandle + Send + Sync>(
                                 (block_x0..block_x1)
                                     .step_by(INNER_STAMP_WIDTH as usize)
                                     .for_each(|pixel_x| {
-                                        let tri_mask = cx0.cmp_ge(f32x8::ZERO)
-                                            & cx1.cmp_ge(f32x8::ZERO)
-                                            & cx2.cmp_ge(f32x8::ZERO);
+                                        let tri_mask = covers(&cx0, top_left[0])
+                                            & covers(&cx1, top_left[1])
+                                            & covers(&cx2, top_left[2])
+                                            & (pixel_x as f32 + *STAMP_OFFSET_X).cmp_lt(max_x_vec);
                                         if tri_mask.any() {
                                             let pixel_zs = tri_mask.blend(
-                                                get_interpolated_z_simd(
-                                                    &triangle, &cx0, &cx1, &cx2,
-                                                ),
+                                                get_interpolated_z_simd(triangle, &cx0, &cx1, &cx2),
                                                 *DEPTH_FAIL,
                                             );
                                             unsafe {
+                                                //Immediately shaded fragments only write depth once kept
+                                                let deferred =
+                                                    matches!(sink, FragmentSink::Deferred(_));
                                                 if let Some(depth_pass) = (*fuwa.0)
-                                                    .try_set_depth_simd(pixel_x, pixel_y, &pixel_zs)
-                                                {
-                                                    let interpolants = interpolate_triangle_simd(
-                                                        &triangle, &cx0, &cx1, &cx2, pixel_zs,
-                                                    );
-                                                    (*fuwa.0).set_fragments_simd(
+                                                    .try_set_depth_stencil_simd(
                                                         pixel_x,
                                                         pixel_y,
-                                                        interpolants,
-                                                        depth_pass,
-                                                        fs_index,
-                                                        slab_ptr,
+                                                        &pixel_zs,
+                                                        triangle.front_facing,
+                                                        deferred,
+                                                    )
+                                                {
+                                                    let interpolants = interpolate_triangle_simd(
+                                                        triangle,
+                                                        &gradients,
+                                                        (pixel_x, pixel_y),
+                                                        &pixel_zs,
+                                                        &cx0,
+                                                        &cx1,
+                                                        &cx2,
                                                     );
+                                                    match sink {
+                                                        FragmentSink::Deferred(slab_ptr) => {
+                                                            (*fuwa.0).set_fragments_simd(
+                                                                pixel_x,
+                                                                pixel_y,
+                                                                interpolants,
+                                                                depth_pass,
+                                                                draw_key,
+                                                                slab_ptr,
+                                                            )
+                                                        }
+                                                        FragmentSink::Immediate(shader) => {
+                                                            (*fuwa.0).shade_fragments_simd(
+                                                                (pixel_x, pixel_y),
+                                                                interpolants,
+                                                                (&pixel_zs, depth_pass),
+                                                                triangle.front_facing,
+                                                                draw_key,
+                                                                shader,
+                                                            )
+                                                        }
+                                                    }
                                                 }
                                             }
                                         }
@@ -234,11 +227,20 @@ impl BlockEdgeResult {
     }
 }
 
-fn get_interp_values(w0: f32, w1: f32, w2: f32) -> (f32, f32) {
-    let weight_sum = w0 + w1 + w2;
-    let l1 = w1 / weight_sum;
-    let l2 = w2 / weight_sum;
-    (l1, l2)
+//Edges are top or left ones when the inside of the triangle is below or to the right
+fn is_top_left(dx: f32, dy: f32) -> bool {
+    dy < 0. || (dy == 0. && dx > 0.)
+}
+
+//Pixels right on an edge only belong to the triangle if it's a top or left edge,
+//so triangles sharing an edge never both draw them
+fn covers(edge: &f32x8, top_left: bool) -> f32x8 {
+    if top_left {
+        edge.cmp_ge(f32x8::ZERO)
+    } else {
+        //wide's cmp_gt tests >= on the upper four lanes without AVX
+        f32x8::ZERO.cmp_lt(*edge)
+    }
 }
 
 fn get_interp_values_simd(w0: &f32x8, w1: &f32x8, w2: &f32x8) -> (f32x8, f32x8) {
@@ -261,91 +263,87 @@ fn get_interpolated_z_simd<F: FSInput>(
     *z0 + (l1 * *zs10) + (l2 * *zs20)
 }
 
+//Screen space gradients of the interpolants divided by w (N) and of 1/w (Z),
+//both of which are affine across the triangle. The interpolants themselves
+//are N / Z, so their derivatives are (dN - F * dZ) / Z. Linear and flat
+//interpolants never get divided by w, so theirs are just dN.
+struct InterpolantGradients<F> {
+    n_dx: F,
+    n_dy: F,
+    z_dx: f32,
+    z_dy: f32,
+}
+
+impl<F: FSInput> InterpolantGradients<F> {
+    //weight_sum is the sum of the edge functions, the same at every pixel
+    fn new(
+        triangle: &Triangle<F>,
+        weight_sum: f32,
+        (dx20, dy20): (f32, f32),
+        (dx01, dy01): (f32, f32),
+    ) -> Self {
+        let [_, sub10, sub20] = triangle.get_interpolate_diffs();
+        let [_, ws10, ws20] = triangle.get_w_diffs();
+
+        //Edge functions decrease by dy when stepping along x and increase by dx along y
+        let (l1_dx, l1_dy) = (-dy20 / weight_sum, dx20 / weight_sum);
+        let (l2_dx, l2_dy) = (-dy01 / weight_sum, dx01 / weight_sum);
+
+        Self {
+            n_dx: (*sub10 * l1_dx) + (*sub20 * l2_dx),
+            n_dy: (*sub10 * l1_dy) + (*sub20 * l2_dy),
+            z_dx: (ws10 * l1_dx) + (ws20 * l2_dx),
+            z_dy: (ws10 * l1_dy) + (ws20 * l2_dy),
+        }
+    }
+
+    fn get_derivatives(&self, interpolant: F, pixel_w: f32) -> FragmentDerivatives<F> {
+        FragmentDerivatives {
+            ddx: (self.n_dx - interpolant.scale_by_mode(self.z_dx, 0.)).scale_by_mode(pixel_w, 1.),
+            ddy: (self.n_dy - interpolant.scale_by_mode(self.z_dy, 0.)).scale_by_mode(pixel_w, 1.),
+        }
+    }
+}
+
+//Also fills in the built-in inputs, for the stamp starting at pixel_x
 fn interpolate_triangle_simd<F: FSInput>(
     triangle: &Triangle<F>,
+    gradients: &InterpolantGradients<F>,
+    (pixel_x, pixel_y): (u32, u32),
+    pixel_zs: &f32x8,
     w0: &f32x8,
     w1: &f32x8,
     w2: &f32x8,
-    pixel_zs: f32x8,
-) -> [F; 8] {
+) -> [Fragment<F>; 8] {
     //optick::event!();
     let (l1, l2) = get_interp_values_simd(w0, w1, w2);
     let [p0, sub10, sub20] = triangle.get_interpolate_diffs();
+    let [pw0, ws10, ws20] = triangle.get_w_diffs();
 
-    let pixel_zs = cast::<_, [f32; 8]>(1. / pixel_zs);
+    let pixel_ws = cast::<_, [f32; 8]>(1. / (*pw0 + (l1 * *ws10) + (l2 * *ws20)));
     let l1_vec = cast::<_, [f32; 8]>(l1);
     let l2_vec = cast::<_, [f32; 8]>(l2);
-
-    [
-        (*p0 + (*sub10 * l1_vec[0]) + (*sub20 * l2_vec[0])) * pixel_zs[0],
-        (*p0 + (*sub10 * l1_vec[1]) + (*sub20 * l2_vec[1])) * pixel_zs[1],
-        (*p0 + (*sub10 * l1_vec[2]) + (*sub20 * l2_vec[2])) * pixel_zs[2],
-        (*p0 + (*sub10 * l1_vec[3]) + (*sub20 * l2_vec[3])) * pixel_zs[3],
-        (*p0 + (*sub10 * l1_vec[4]) + (*sub20 * l2_vec[4])) * pixel_zs[4],
-        (*p0 + (*sub10 * l1_vec[5]) + (*sub20 * l2_vec[5])) * pixel_zs[5],
-        (*p0 + (*sub10 * l1_vec[6]) + (*sub20 * l2_vec[6])) * pixel_zs[6],
-        (*p0 + (*sub10 * l1_vec[7]) + (*sub20 * l2_vec[7])) * pixel_zs[7],
-    ]
-}
-
-fn get_interpolated_z_block<F: FSInput>(
-    triangle: &Triangle<F>,
-    w00: (f32, f32, f32),
-    dx: (f32, f32, f32),
-    dy: (f32, f32, f32),
-    width: u32,
-    height: u32,
-) -> Vec<f32> {
-    //optick::event!();
-    let [z0, zs10, zs20] = triangle.get_z_diffs();
-
-    let mut left_interpolator = Vec3A::from(w00);
-    let step_x = Vec3A::from(dy);
-    let step_y = Vec3A::from(dx);
-
-    let mut out = Vec::with_capacity((width * height) as usize);
-    for _ in 0..height {
-        let mut x_interpolator = left_interpolator;
-        for _ in 0..width {
-            let (l1, l2) =
-                get_interp_values(x_interpolator[0], x_interpolator[1], x_interpolator[2]);
-            out.push(z0 + (l1 * zs10) + (l2 * zs20));
-            x_interpolator -= step_x;
-        }
-        left_interpolator += step_y;
-    }
-    out
-}
-
-//TODO: Perf this
-fn get_interpolated_triangle_block<F: FSInput>(
-    triangle: &Triangle<F>,
-    w00: (f32, f32, f32),
-    dx: (f32, f32, f32),
-    dy: (f32, f32, f32),
-    (width, height): (u32, u32),
-    depth_pass: &[Option<f32>],
-) -> Vec<F> {
-    let mut left_interpolator = Vec3A::from(w00);
-    let step_x = Vec3A::from(dy);
-    let step_y = Vec3A::from(dx);
-    let mut counter: usize = 0;
-    let [p0s, sub10, sub20] = triangle.get_interpolate_diffs();
-
-    let mut out = Vec::with_capacity((width * height) as usize);
-    for _ in 0..height {
-        let mut x_interpolator = left_interpolator;
-        for _ in 0..width {
-            if let Some(pixel_depth) = depth_pass[counter] {
-                let pixel_depth = pixel_depth.recip();
-                let (l1, l2) =
-                    get_interp_values(x_interpolator[0], x_interpolator[1], x_interpolator[2]);
-                out.push((*p0s + (*sub10 * l1) + (*sub20 * l2)) * pixel_depth);
-            }
-            x_interpolator -= step_x;
-            counter += 1;
+    let zs = cast::<_, [f32; 8]>(*pixel_zs);
+    let [b0, b1, b2] = triangle.barycentrics;
+
+    std::array::from_fn(|lane| {
+        let input = (*p0 + (*sub10 * l1_vec[lane]) + (*sub20 * l2_vec[lane]))
+            .scale_by_mode(pixel_ws[lane], 1.);
+        Fragment {
+            input,
+            context: FragmentContext {
+                frag_coord: vec4(
+                    (pixel_x + lane as u32) as f32,
+                    pixel_y as f32,
+                    zs[lane],
+                    pixel_ws[lane].recip(),
+                ),
+                front_facing: triangle.front_facing,
+                barycentrics: (b0 + (b1 - b0) * l1_vec[lane] + (b2 - b0) * l2_vec[lane])
+                    * pixel_ws[lane],
+                primitive_id: triangle.primitive_id,
+                derivatives: gradients.get_derivatives(input, pixel_ws[lane]),
+            },
         }
-        left_interpolator += step_y;
-    }
-    out
+    })
 }
@@ -0,0 +1,69 @@
+use glam::*;
+
+/// Derives FragmentOutput for structs of Vec4s, writing each field
+/// to the color attachment at the same index, in declaration order.
+pub use fuwa_derive::FragmentOutput;
+
+/// What a fragment shader writes, one linear color per color attachment of the
+/// bound render texture. The window only has one.
+pub trait FragmentOutput: Copy + Send + Sync {
+    /// Number of colors. Attachments past the last color are left untouched,
+    /// and colors past the last attachment are dropped.
+    const COUNT: usize;
+
+    fn color(&self, index: usize) -> Vec4;
+}
+
+/// Writes no color at all, like a depth only pass.
+impl FragmentOutput for () {
+    const COUNT: usize = 0;
+
+    fn color(&self, _: usize) -> Vec4 {
+        unreachable!()
+    }
+}
+
+impl FragmentOutput for Vec4 {
+    const COUNT: usize = 1;
+
+    #[inline]
+    fn color(&self, _: usize) -> Vec4 {
+        *self
+    }
+}
+
+impl<const N: usize> FragmentOutput for [Vec4; N] {
+    const COUNT: usize = N;
+
+    #[inline]
+    fn color(&self, index: usize) -> Vec4 {
+        self[index]
+    }
+}
+
+impl FragmentOutput for (Vec4, Vec4) {
+    const COUNT: usize = 2;
+
+    #[inline]
+    fn color(&self, index: usize) -> Vec4 {
+        [self.0, self.1][index]
+    }
+}
+
+impl FragmentOutput for (Vec4, Vec4, Vec4) {
+    const COUNT: usize = 3;
+
+    #[inline]
+    fn color(&self, index: usize) -> Vec4 {
+        [self.0, self.1, self.2][index]
+    }
+}
+
+impl FragmentOutput for (Vec4, Vec4, Vec4, Vec4) {
+    const COUNT: usize = 4;
+
+    #[inline]
+    fn color(&self, index: usize) -> Vec4 {
+        [self.0, self.1, self.2, self.3][index]
+    }
+}
@@ -1,12 +1,16 @@
-use crate::{Texture, Uniforms};
+use crate::{FragmentOutput, Handle, Sampler, Texture, Uniforms};
 use glam::*;
-use lazy_static::lazy_static;
 use std::ops::*;
 
-lazy_static! {
-    static ref U8FASTVEC: Vec3A = Vec3A::splat(255.);
-}
+/// Derives FSInput for structs of f32s and glam vectors, so any combination
+/// of them can be passed from the vertex to the fragment shader.
+/// Fields are interpolated with perspective correction unless marked with
+/// `#[interpolate(noperspective)]`, which interpolates linearly in screen space,
+/// or `#[interpolate(flat)]`, which takes the value of the first vertex of the triangle.
+pub use fuwa_derive::FSInput;
 
+/// Values interpolated across triangles, from the vertex to the fragment shader.
+/// Everything is interpolated with perspective correction unless overridden.
 pub trait FSInput:
     Clone
     + Copy
@@ -27,15 +31,83 @@ pub trait FSInput:
     + DivAssign<f32>
     + 'static
 {
+    /// Multiplies the fields interpolated with perspective correction by `perspective`,
+    /// and the linearly interpolated and flat fields by `linear`.
+    #[inline]
+    fn scale_by_mode(self, perspective: f32, _linear: f32) -> Self {
+        self * perspective
+    }
+
+    /// Sets the flat fields to those of the triangle's provoking vertex.
+    #[inline]
+    fn copy_flat(&mut self, _provoking: &Self) {}
 }
-impl FSInput for Vec3A {}
+impl FSInput for f32 {}
 impl FSInput for Vec2 {}
+impl FSInput for Vec3 {}
+impl FSInput for Vec3A {}
+impl FSInput for Vec4 {}
+
+/// Rates of change of the fragment shader inputs between neighbouring pixels,
+/// along the screen's x and y axis.
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentDerivatives<F> {
+    pub ddx: F,
+    pub ddy: F,
+}
+
+/// Built-in inputs of a fragment, which don't have to be passed through the vertex shader.
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentContext<F> {
+    /// The pixel's x and y in the framebuffer, its depth after the
+    /// viewport transform, and 1/w of the interpolated clip space position.
+    pub frag_coord: Vec4,
+    /// Whether the triangle faces the camera, or only got drawn because nothing culled it.
+    pub front_facing: bool,
+    /// Perspective correct weights of the triangle's vertices, in the order they were indexed.
+    pub barycentrics: Vec3,
+    /// Index of the triangle in the draw's index list.
+    pub primitive_id: u32,
+    pub derivatives: FragmentDerivatives<F>,
+}
 
 pub trait FragmentShader<F: FSInput>: Send + Sync + Clone {
-    fn fragment_shader_fn(&self, fs_in: F, uniforms: &Uniforms) -> [u8; 4];
+    /// A Vec4 for a single color, or one per color attachment of the render texture.
+    type Output: FragmentOutput;
+
+    /// Shaders which can return None have to set this, so they get run as their
+    /// fragments are drawn and discarded fragments never write depth or stencil.
+    /// Otherwise their fragments are left to render, which only shades the ones
+    /// still visible by then.
+    const CAN_DISCARD: bool = false;
+
+    /// Returns the linear colors of the fragment, or None to discard it.
+    /// The format of each attachment decides how its color gets encoded.
+    /// Discarded fragments leave the color, depth and stencil buffers untouched,
+    /// as long as CAN_DISCARD is set.
+    fn fragment_shader_fn(
+        &self,
+        fs_in: F,
+        context: &FragmentContext<F>,
+        uniforms: &Uniforms,
+    ) -> Option<Self::Output>;
+
+    /// Shades the fragments of 8 neighbouring pixels as they're drawn, with None for
+    /// the lanes without one. Runs fragment_shader_fn per lane, unless overridden
+    /// to shade them together, like SimdShader does.
+    fn fragment_shader_stamp_fn(
+        &self,
+        fragments: &[Option<(F, FragmentContext<F>)>; 8],
+        uniforms: &Uniforms,
+    ) -> [Option<Self::Output>; 8] {
+        std::array::from_fn(|lane| {
+            let (fs_in, context) = fragments[lane].as_ref()?;
+            self.fragment_shader_fn(*fs_in, context, uniforms)
+        })
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct ColorBlend {}
 impl ColorBlend {
     pub fn new() -> Self {
@@ -43,49 +115,74 @@ impl ColorBlend {
     }
 }
 impl FragmentShader<Vec3A> for ColorBlend {
-    fn fragment_shader_fn(&self, fs_in: Vec3A, _: &Uniforms) -> [u8; 4] {
-        let c = fs_in * *U8FASTVEC;
-        [c[0] as u8, c[1] as u8, c[2] as u8, 0xFF]
+    type Output = Vec4;
+
+    fn fragment_shader_fn(
+        &self,
+        fs_in: Vec3A,
+        _: &FragmentContext<Vec3A>,
+        _: &Uniforms,
+    ) -> Option<Vec4> {
+        Some(fs_in.extend(1.))
     }
 }
 
 #[derive(Clone)]
 pub struct Textured {
-    texture_handle: usize,
+    texture_handle: Handle<Texture>,
+    sampler_handle: Option<Handle<Sampler>>,
 }
 
 impl Textured {
-    pub fn new(texture_handle: usize) -> Self {
-        Self { texture_handle }
+    pub fn new(texture_handle: Handle<Texture>) -> Self {
+        Self {
+            texture_handle,
+            sampler_handle: None,
+        }
     }
 
-    pub fn set_texture_handle(&mut self, texture_handle: usize) {
+    pub fn set_texture_handle(&mut self, texture_handle: Handle<Texture>) {
         self.texture_handle = texture_handle
     }
 
-    pub fn get_texture_handle(&self) -> usize {
+    pub fn get_texture_handle(&self) -> Handle<Texture> {
         self.texture_handle
     }
+
+    /// Samples with the given sampler, or Sampler::DEFAULT if None.
+    pub fn set_sampler_handle(&mut self, sampler_handle: Option<Handle<Sampler>>) {
+        self.sampler_handle = sampler_handle
+    }
+
+    pub fn get_sampler_handle(&self) -> Option<Handle<Sampler>> {
+        self.sampler_handle
+    }
 }
 
 impl FragmentShader<Vec2> for Textured {
-    fn fragment_shader_fn(&self, fs_in: Vec2, uniforms: &Uniforms) -> [u8; 4] {
-        sample_2d(
-            fs_in[0],
-            fs_in[1],
-            uniforms.get_texture(self.texture_handle),
+    type Output = Vec4;
+
+    fn fragment_shader_fn(
+        &self,
+        fs_in: Vec2,
+        context: &FragmentContext<Vec2>,
+        uniforms: &Uniforms,
+    ) -> Option<Vec4> {
+        let sampler = match self.sampler_handle {
+            Some(handle) => uniforms
+                .get_sampler(handle)
+                .expect("Invalid sampler handle!"),
+            None => &Sampler::DEFAULT,
+        };
+        Some(
+            sampler.sample_grad(
+                uniforms
+                    .get_texture(self.texture_handle)
+                    .expect("Invalid texture handle!"),
+                fs_in,
+                context.derivatives.ddx,
+                context.derivatives.ddy,
+            ),
         )
     }
 }
-
-fn sample_2d(u: f32, v: f32, texture: &Texture) -> [u8; 4] {
-    let u = (u * (texture.width - 1) as f32).round() as u32;
-    let v = (v * (texture.height - 1) as f32).round() as u32;
-    let index = 4 * (u + (v * texture.width)) as usize;
-    [
-        texture.data[index],
-        texture.data[index + 1],
-        texture.data[index + 2],
-        texture.data[index + 3],
-    ]
-}
@@ -0,0 +1,287 @@
+use crate::rasterization::Fragment;
+use crate::{FSInput, FragmentContext, FragmentShader, Uniforms};
+use bytemuck::cast;
+use glam::*;
+use wide::{f32x8, u32x8};
+
+/// Derives FSInputSimd for structs whose fields all implement it, generating
+/// a `<Name>Lanes` struct with the same fields in their lane layout.
+pub use fuwa_derive::FSInputSimd;
+
+/// Fragment shader inputs which can be laid out as a structure of arrays, one lane per fragment.
+pub trait FSInputSimd: FSInput {
+    type Lanes: Copy + Send + Sync;
+
+    fn to_lanes(values: &[Self; 8]) -> Self::Lanes;
+}
+
+impl FSInputSimd for f32 {
+    type Lanes = f32x8;
+
+    fn to_lanes(values: &[Self; 8]) -> Self::Lanes {
+        f32x8::from(*values)
+    }
+}
+
+impl FSInputSimd for Vec2 {
+    type Lanes = [f32x8; 2];
+
+    fn to_lanes(values: &[Self; 8]) -> Self::Lanes {
+        [
+            lanes_of(values, |value| value.x()),
+            lanes_of(values, |value| value.y()),
+        ]
+    }
+}
+
+impl FSInputSimd for Vec3 {
+    type Lanes = [f32x8; 3];
+
+    fn to_lanes(values: &[Self; 8]) -> Self::Lanes {
+        [
+            lanes_of(values, |value| value.x()),
+            lanes_of(values, |value| value.y()),
+            lanes_of(values, |value| value.z()),
+        ]
+    }
+}
+
+impl FSInputSimd for Vec3A {
+    type Lanes = [f32x8; 3];
+
+    fn to_lanes(values: &[Self; 8]) -> Self::Lanes {
+        [
+            lanes_of(values, |value| value.x()),
+            lanes_of(values, |value| value.y()),
+            lanes_of(values, |value| value.z()),
+        ]
+    }
+}
+
+impl FSInputSimd for Vec4 {
+    type Lanes = [f32x8; 4];
+
+    fn to_lanes(values: &[Self; 8]) -> Self::Lanes {
+        [
+            lanes_of(values, |value| value.x()),
+            lanes_of(values, |value| value.y()),
+            lanes_of(values, |value| value.z()),
+            lanes_of(values, |value| value.w()),
+        ]
+    }
+}
+
+fn lanes_of<T>(values: &[T; 8], channel: impl Fn(&T) -> f32) -> f32x8 {
+    f32x8::from(std::array::from_fn::<f32, 8, _>(|lane| {
+        channel(&values[lane])
+    }))
+}
+
+//Lanes are set to all ones, like the results of f32x8 comparisons
+fn mask_of(set: impl Fn(usize) -> bool) -> f32x8 {
+    cast(std::array::from_fn::<i32, 8, _>(|lane| -(set(lane) as i32)))
+}
+
+/// Up to 8 fragments drawn with the same uniform bindings, with every value
+/// of their FragmentContext split into lanes.
+#[derive(Copy, Clone)]
+pub struct FragmentBatch<F: FSInputSimd> {
+    pub inputs: F::Lanes,
+    pub ddx: F::Lanes,
+    pub ddy: F::Lanes,
+    /// The x, y, z and w of each fragment's frag coord.
+    pub frag_coord: [f32x8; 4],
+    /// Set for lanes whose triangle faces the camera.
+    pub front_facing: f32x8,
+    pub barycentrics: [f32x8; 3],
+    pub primitive_ids: u32x8,
+    /// Set for lanes holding a fragment. The others hold a copy of one
+    /// which does, so they're safe to shade, and their results are ignored.
+    pub mask: f32x8,
+}
+
+impl<F: FSInputSimd> FragmentBatch<F> {
+    //There has to be at least one fragment
+    pub(crate) fn new(fragments: &[Option<Fragment<F>>; 8]) -> Self {
+        let mask = mask_of(|lane| fragments[lane].is_some());
+        let filler = fragments.iter().flatten().next().unwrap();
+        let fragments: [Fragment<F>; 8] =
+            std::array::from_fn(|lane| fragments[lane].unwrap_or(*filler));
+        let contexts: [FragmentContext<F>; 8] = std::array::from_fn(|lane| fragments[lane].context);
+
+        Self {
+            inputs: F::to_lanes(&std::array::from_fn(|lane| fragments[lane].input)),
+            ddx: F::to_lanes(&std::array::from_fn(|lane| contexts[lane].derivatives.ddx)),
+            ddy: F::to_lanes(&std::array::from_fn(|lane| contexts[lane].derivatives.ddy)),
+            frag_coord: Vec4::to_lanes(&std::array::from_fn(|lane| contexts[lane].frag_coord)),
+            front_facing: mask_of(|lane| contexts[lane].front_facing),
+            barycentrics: Vec3::to_lanes(&std::array::from_fn(|lane| contexts[lane].barycentrics)),
+            primitive_ids: cast(std::array::from_fn::<u32, 8, _>(|lane| {
+                contexts[lane].primitive_id
+            })),
+            mask,
+        }
+    }
+}
+
+/// Shades 8 fragments at a time, for shaders which are worth vectorizing.
+pub trait FragmentShaderSimd<F: FSInputSimd>: Send + Sync + Clone {
+    /// Same as FragmentShader::CAN_DISCARD. Shaders which can discard get
+    /// run as they're drawn, still 8 lanes at a time.
+    const CAN_DISCARD: bool = false;
+
+    /// Returns the r, g, b and a of every lane's linear color, and a mask of
+    /// the lanes to keep. Cleared lanes get discarded, like returning None
+    /// from a FragmentShader.
+    fn fragment_shader_simd_fn(
+        &self,
+        batch: &FragmentBatch<F>,
+        uniforms: &Uniforms,
+    ) -> ([f32x8; 4], f32x8);
+}
+
+/// Wraps a FragmentShaderSimd, so it can be added, drawn with and rendered
+/// like any other fragment shader. Render it with render_simd to shade
+/// 8 fragments at a time, as render runs it one lane at a time.
+/// Fragments shaded as they're drawn always go 8 at a time.
+/// Only writes to the first color attachment.
+#[derive(Clone)]
+pub struct SimdShader<S>(pub S);
+
+impl<F: FSInputSimd, S: FragmentShaderSimd<F>> FragmentShader<F> for SimdShader<S> {
+    type Output = Vec4;
+    const CAN_DISCARD: bool = <S as FragmentShaderSimd<F>>::CAN_DISCARD;
+
+    fn fragment_shader_fn(
+        &self,
+        fs_in: F,
+        context: &FragmentContext<F>,
+        uniforms: &Uniforms,
+    ) -> Option<Vec4> {
+        let mut fragments = [None; 8];
+        fragments[0] = Some((fs_in, *context));
+        self.fragment_shader_stamp_fn(&fragments, uniforms)[0]
+    }
+
+    fn fragment_shader_stamp_fn(
+        &self,
+        fragments: &[Option<(F, FragmentContext<F>)>; 8],
+        uniforms: &Uniforms,
+    ) -> [Option<Vec4>; 8] {
+        let fragments =
+            fragments.map(|fragment| fragment.map(|(input, context)| Fragment { input, context }));
+        if fragments.iter().all(Option::is_none) {
+            return [None; 8];
+        }
+
+        let (color, keep) = self
+            .0
+            .fragment_shader_simd_fn(&FragmentBatch::new(&fragments), uniforms);
+        let keep = keep.move_mask();
+        std::array::from_fn(|lane| {
+            (fragments[lane].is_some() && keep & 1 << lane != 0).then(|| lane_color(&color, lane))
+        })
+    }
+}
+
+pub(crate) fn lane_color(color: &[f32x8; 4], lane: usize) -> Vec4 {
+    let [r, g, b, a] = *color;
+    vec4(
+        cast::<_, [f32; 8]>(r)[lane],
+        cast::<_, [f32; 8]>(g)[lane],
+        cast::<_, [f32; 8]>(b)[lane],
+        cast::<_, [f32; 8]>(a)[lane],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FragmentDerivatives, UniformStorage};
+
+    //Keeps the lanes whose input is above a half, with the input in red
+    //and each lane's x in green
+    #[derive(Clone)]
+    struct AboveHalf;
+
+    impl FragmentShaderSimd<f32> for AboveHalf {
+        const CAN_DISCARD: bool = true;
+
+        fn fragment_shader_simd_fn(
+            &self,
+            batch: &FragmentBatch<f32>,
+            _uniforms: &Uniforms,
+        ) -> ([f32x8; 4], f32x8) {
+            let keep = f32x8::splat(0.5).cmp_lt(batch.inputs);
+            let color = [batch.inputs, batch.frag_coord[0], f32x8::ZERO, f32x8::ONE];
+            (color, keep)
+        }
+    }
+
+    fn fragment(lane: usize, input: f32) -> (f32, FragmentContext<f32>) {
+        let context = FragmentContext {
+            frag_coord: vec4(lane as f32, 0., 0.5, 1.),
+            front_facing: lane != 3,
+            barycentrics: Vec3::one() / 3.,
+            primitive_id: lane as u32,
+            derivatives: FragmentDerivatives { ddx: 0., ddy: 0. },
+        };
+        (input, context)
+    }
+
+    #[test]
+    fn batches_mark_and_fill_the_missing_lanes() {
+        let mut fragments = [None; 8];
+        for &lane in [2, 3, 6].iter() {
+            let (input, context) = fragment(lane, lane as f32);
+            fragments[lane] = Some(Fragment { input, context });
+        }
+        let batch = FragmentBatch::new(&fragments);
+
+        assert_eq!(batch.mask.move_mask(), 0b0100_1100);
+        //Missing lanes are copies of the first fragment, and only lane 3 faces away
+        assert_eq!(batch.front_facing.move_mask(), 0b1111_0111);
+        let inputs = cast::<_, [f32; 8]>(batch.inputs);
+        assert_eq!(inputs, [2., 2., 2., 3., 2., 2., 6., 2.]);
+        let ids = cast::<_, [u32; 8]>(batch.primitive_ids);
+        assert_eq!(ids, [2, 2, 2, 3, 2, 2, 6, 2]);
+    }
+
+    #[test]
+    fn stamps_keep_each_lanes_output_and_drop_discarded_lanes() {
+        let inputs = [0.9, 0.1, 0.6, 0.7, 0.2, 0.8, 0.4, 1.];
+        let mut fragments: [Option<(f32, FragmentContext<f32>)>; 8] =
+            std::array::from_fn(|lane| Some(fragment(lane, inputs[lane])));
+        //Lanes without a fragment never produce output, whatever the shader keeps
+        fragments[5] = None;
+
+        let storage = UniformStorage::new();
+        let outputs = SimdShader(AboveHalf).fragment_shader_stamp_fn(&fragments, &storage.bound());
+
+        for (lane, output) in outputs.iter().enumerate() {
+            let expected =
+                (lane != 5 && inputs[lane] > 0.5).then(|| vec4(inputs[lane], lane as f32, 0., 1.));
+            assert_eq!(*output, expected, "lane {}", lane);
+        }
+        assert_eq!(
+            SimdShader(AboveHalf).fragment_shader_stamp_fn(&[None; 8], &storage.bound()),
+            [None; 8]
+        );
+    }
+
+    #[test]
+    fn single_fragments_go_through_the_first_lane() {
+        let storage = UniformStorage::new();
+        let shader = SimdShader(AboveHalf);
+        let (input, context) = fragment(3, 0.75);
+        assert_eq!(
+            shader.fragment_shader_fn(input, &context, &storage.bound()),
+            Some(vec4(0.75, 3., 0., 1.))
+        );
+        let (input, context) = fragment(3, 0.25);
+        assert_eq!(
+            shader.fragment_shader_fn(input, &context, &storage.bound()),
+            None
+        );
+    }
+}
@@ -1,5 +1,11 @@
 pub mod fragment_shader;
 pub use fragment_shader::*;
 
+pub mod fragment_output;
+pub use fragment_output::*;
+
+pub mod fragment_shader_simd;
+pub use fragment_shader_simd::*;
+
 pub mod vertex_shader;
 pub use vertex_shader::*;
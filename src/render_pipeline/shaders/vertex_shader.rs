@@ -1,4 +1,5 @@
 //TODO: Write this struct
+use crate::Uniforms;
 use glam::*;
 
 pub trait VSInput: Send + Sync {}
@@ -7,37 +8,78 @@ impl VSInput for [f32; 6] {}
 
 //pub type VertexShaderFunction<VSIn, VSOut> = Box<dyn Fn(VSIn) -> (Vec3A, VSOut) + Send + Sync>;
 
+/// Vertex shaders output a clip space position, the perspective divide and
+/// viewport transform are handled by the pipeline.
 pub trait VertexShader<VSIn, VSOut>: Send + Sync {
-    fn vertex_shader_fn(&self, raw_vertex_data: &VSIn) -> (Vec3A, VSOut);
+    fn vertex_shader_fn(&self, raw_vertex_data: &VSIn, uniforms: &Uniforms) -> (Vec4, VSOut);
 }
 
+/// Uniform block read by the BasicVertexShader.
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    pub rotation: Mat3,
+    pub translation: Vec3A,
+    pub projection: Mat4,
+}
+
+impl Transform {
+    fn apply(&self, position: Vec3A) -> Vec4 {
+        self.projection * (self.rotation * position + self.translation).extend(1.)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            rotation: Mat3::default(),
+            translation: Vec3A::default(),
+            projection: Mat4::perspective_lh(std::f32::consts::FRAC_PI_2, 1., 0.1, 1000.),
+        }
+    }
+}
+
+/// Uses the bound Transform uniform block, or its own transform if there is none.
 pub struct BasicVertexShader {
-    rotation: Mat3,
-    translation: Vec3A,
+    transform: Transform,
 }
 
 impl BasicVertexShader {
     pub fn bind_rotation(&mut self, rotation: Mat3) {
-        self.rotation = rotation;
+        self.transform.rotation = rotation;
     }
 
     pub fn bind_translation(&mut self, translation: Vec3A) {
-        self.translation = translation;
+        self.transform.translation = translation;
+    }
+
+    pub fn bind_projection(&mut self, projection: Mat4) {
+        self.transform.projection = projection;
     }
 
     pub fn new() -> Self {
         Self {
-            rotation: Mat3::default(),
-            translation: Vec3A::default(),
+            transform: Transform::default(),
         }
     }
+
+    fn transform<'a>(&'a self, uniforms: &Uniforms<'a>) -> &'a Transform {
+        uniforms.get_block().unwrap_or(&self.transform)
+    }
+}
+
+impl Default for BasicVertexShader {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VertexShader<[f32; 5], Vec2> for BasicVertexShader {
-    fn vertex_shader_fn(&self, raw_vertex_data: &[f32; 5]) -> (Vec3A, Vec2) {
-        let position = self.rotation
-            * vec3a(raw_vertex_data[0], raw_vertex_data[1], raw_vertex_data[2])
-            + self.translation;
+    fn vertex_shader_fn(&self, raw_vertex_data: &[f32; 5], uniforms: &Uniforms) -> (Vec4, Vec2) {
+        let position = self.transform(uniforms).apply(vec3a(
+            raw_vertex_data[0],
+            raw_vertex_data[1],
+            raw_vertex_data[2],
+        ));
         let output = vec2(raw_vertex_data[3], raw_vertex_data[4]);
 
         (position, output)
@@ -45,10 +87,12 @@ impl VertexShader<[f32; 5], Vec2> for BasicVertexShader {
 }
 
 impl VertexShader<[f32; 6], Vec3A> for BasicVertexShader {
-    fn vertex_shader_fn(&self, raw_vertex_data: &[f32; 6]) -> (Vec3A, Vec3A) {
-        let position = self.rotation
-            * vec3a(raw_vertex_data[0], raw_vertex_data[1], raw_vertex_data[2])
-            + self.translation;
+    fn vertex_shader_fn(&self, raw_vertex_data: &[f32; 6], uniforms: &Uniforms) -> (Vec4, Vec3A) {
+        let position = self.transform(uniforms).apply(vec3a(
+            raw_vertex_data[0],
+            raw_vertex_data[1],
+            raw_vertex_data[2],
+        ));
         let output = vec3a(raw_vertex_data[3], raw_vertex_data[4], raw_vertex_data[5]);
 
         (position, output)
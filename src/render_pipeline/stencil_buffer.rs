@@ -0,0 +1,242 @@
+use super::CompareFunction;
+
+//Extra room at the end, so 8 wide stamps on the last row stay in bounds
+const STAMP_PADDING: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StencilOperation {
+    Keep,
+    Zero,
+    Replace,
+    Invert,
+    IncrementClamp,
+    DecrementClamp,
+    IncrementWrap,
+    DecrementWrap,
+}
+
+impl StencilOperation {
+    pub fn apply(self, value: u8, reference: u8) -> u8 {
+        match self {
+            Self::Keep => value,
+            Self::Zero => 0,
+            Self::Replace => reference,
+            Self::Invert => !value,
+            Self::IncrementClamp => value.saturating_add(1),
+            Self::DecrementClamp => value.saturating_sub(1),
+            Self::IncrementWrap => value.wrapping_add(1),
+            Self::DecrementWrap => value.wrapping_sub(1),
+        }
+    }
+}
+
+/// Stencil test and operations for one facing of a triangle.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StencilFaceState {
+    pub compare: CompareFunction,
+    pub fail_op: StencilOperation,
+    pub depth_fail_op: StencilOperation,
+    pub pass_op: StencilOperation,
+}
+
+impl Default for StencilFaceState {
+    fn default() -> Self {
+        Self {
+            compare: CompareFunction::Always,
+            fail_op: StencilOperation::Keep,
+            depth_fail_op: StencilOperation::Keep,
+            pass_op: StencilOperation::Keep,
+        }
+    }
+}
+
+/// Fragments pass the stencil test when
+/// `(reference & read_mask) <compare> (stencil & read_mask)` holds.
+/// Only bits set in the write mask are changed by the stencil operations.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StencilState {
+    pub enabled: bool,
+    pub front: StencilFaceState,
+    pub back: StencilFaceState,
+    pub reference: u8,
+    pub read_mask: u8,
+    pub write_mask: u8,
+}
+
+impl Default for StencilState {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            front: StencilFaceState::default(),
+            back: StencilFaceState::default(),
+            reference: 0,
+            read_mask: 0xFF,
+            write_mask: 0xFF,
+        }
+    }
+}
+
+impl StencilState {
+    pub fn get_face(&self, front_facing: bool) -> &StencilFaceState {
+        if front_facing {
+            &self.front
+        } else {
+            &self.back
+        }
+    }
+}
+
+pub(crate) struct StencilBuffer {
+    pub(crate) stencil_buffer: Vec<u8>,
+    pub(crate) clear_value: u8,
+}
+
+impl StencilBuffer {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        Self {
+            stencil_buffer: vec![0; (width * height) as usize + STAMP_PADDING],
+            clear_value: 0,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        unsafe {
+            let target = self.stencil_buffer.as_mut_ptr();
+            let len = self.stencil_buffer.len();
+            std::ptr::write_bytes(target, self.clear_value, len)
+        }
+    }
+
+    pub(crate) fn test(&self, index: usize, state: &StencilState, face: &StencilFaceState) -> bool {
+        let value = unsafe { *self.stencil_buffer.get_unchecked(index) };
+        face.compare
+            .compare(state.reference & state.read_mask, value & state.read_mask)
+    }
+
+    //Applies the operation picked by which of the tests the fragment passed
+    pub(crate) fn update(
+        &mut self,
+        index: usize,
+        (stencil_pass, depth_pass): (bool, bool),
+        state: &StencilState,
+        face: &StencilFaceState,
+    ) {
+        let operation = if !stencil_pass {
+            face.fail_op
+        } else if !depth_pass {
+            face.depth_fail_op
+        } else {
+            face.pass_op
+        };
+
+        unsafe {
+            let value = self.stencil_buffer.get_unchecked_mut(index);
+            let result = operation.apply(*value, state.reference);
+            *value = (*value & !state.write_mask) | (result & state.write_mask);
+        }
+    }
+
+    //Returns a bitmask of the lanes in coverage_mask which pass the test
+    pub(crate) fn test_simd(
+        &self,
+        index: usize,
+        coverage_mask: i32,
+        state: &StencilState,
+        face: &StencilFaceState,
+    ) -> i32 {
+        (0..8)
+            .filter(|lane| coverage_mask & 1 << lane != 0 && self.test(index + lane, state, face))
+            .fold(0, |pass_mask, lane| pass_mask | 1 << lane)
+    }
+
+    pub(crate) fn update_simd(
+        &mut self,
+        index: usize,
+        coverage_mask: i32,
+        (stencil_pass, depth_pass): (i32, i32),
+        state: &StencilState,
+        face: &StencilFaceState,
+    ) {
+        for lane in (0..8).filter(|lane| coverage_mask & 1 << lane != 0) {
+            let passed = |mask: i32| mask & 1 << lane != 0;
+            self.update(
+                index + lane,
+                (passed(stencil_pass), passed(depth_pass)),
+                state,
+                face,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_wrap_and_clamp() {
+        use StencilOperation::*;
+
+        assert_eq!(Keep.apply(5, 9), 5);
+        assert_eq!(Zero.apply(5, 9), 0);
+        assert_eq!(Replace.apply(5, 9), 9);
+        assert_eq!(Invert.apply(0b1010_0101, 9), 0b0101_1010);
+        assert_eq!(IncrementClamp.apply(255, 9), 255);
+        assert_eq!(DecrementClamp.apply(0, 9), 0);
+        assert_eq!(IncrementWrap.apply(255, 9), 0);
+        assert_eq!(DecrementWrap.apply(0, 9), 255);
+    }
+
+    #[test]
+    fn update_picks_the_operation_and_respects_the_write_mask() {
+        let face = StencilFaceState {
+            compare: CompareFunction::Always,
+            fail_op: StencilOperation::Zero,
+            depth_fail_op: StencilOperation::IncrementClamp,
+            pass_op: StencilOperation::Replace,
+        };
+        let state = StencilState {
+            enabled: true,
+            front: face,
+            back: face,
+            reference: 0xFF,
+            read_mask: 0xFF,
+            write_mask: 0x0F,
+        };
+        let mut buffer = StencilBuffer::new(4, 1);
+        buffer.stencil_buffer[..4].copy_from_slice(&[0x33; 4]);
+
+        //The last lane isn't covered, so it's left alone
+        buffer.update_simd(0, 0b0111, (0b0110, 0b0100), &state, &face);
+        assert_eq!(&buffer.stencil_buffer[..4], &[0x30, 0x34, 0x3F, 0x33]);
+
+        //The scalar path makes the same choices one pixel at a time
+        buffer.stencil_buffer[..4].copy_from_slice(&[0x33; 4]);
+        buffer.update(0, (false, false), &state, &face);
+        buffer.update(1, (true, false), &state, &face);
+        buffer.update(2, (true, true), &state, &face);
+        assert_eq!(&buffer.stencil_buffer[..4], &[0x30, 0x34, 0x3F, 0x33]);
+    }
+
+    #[test]
+    fn test_masks_both_sides() {
+        let face = StencilFaceState {
+            compare: CompareFunction::Equal,
+            ..StencilFaceState::default()
+        };
+        let state = StencilState {
+            enabled: true,
+            front: face,
+            back: face,
+            reference: 0x12,
+            read_mask: 0x0F,
+            write_mask: 0xFF,
+        };
+        let mut buffer = StencilBuffer::new(3, 1);
+        buffer.stencil_buffer[..3].copy_from_slice(&[0xF2, 0x13, 0x02]);
+
+        assert_eq!(buffer.test_simd(0, 0b011, &state, &face), 0b001);
+        assert!(buffer.test(0, &state, &face));
+        assert!(!buffer.test(1, &state, &face));
+    }
+}
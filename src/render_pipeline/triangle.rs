@@ -1,22 +1,33 @@
-use crate::{FSInput, Fuwa};
+use crate::{FSInput, Fuwa, RenderTarget};
 use glam::*;
 use once_cell::sync::OnceCell;
-use raw_window_handle::HasRawWindowHandle;
 
 pub struct Triangle<F> {
-    pub(crate) points: [Vec3A; 3],
+    //Clip space positions, which become (screen x, screen y, depth, 1/w)
+    //once transformed to screen space
+    pub(crate) points: [Vec4; 3],
     pub(crate) vs_input: [F; 3],
+    pub(crate) front_facing: bool,
+    //Weights of the original vertices, which clipped triangles interpolate
+    //like any other perspective correct value
+    pub(crate) barycentrics: [Vec3; 3],
+    pub(crate) primitive_id: u32,
     interpolate_diffs: OnceCell<[F; 3]>,
     z_diffs: OnceCell<[f32; 3]>,
+    w_diffs: OnceCell<[f32; 3]>,
 }
 
 impl<F: FSInput> Triangle<F> {
-    pub(crate) fn new(points: [Vec3A; 3], vs_input: [F; 3]) -> Self {
+    pub(crate) fn new(points: [Vec4; 3], vs_input: [F; 3]) -> Self {
         Self {
             points,
             vs_input,
+            front_facing: true,
+            barycentrics: [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()],
+            primitive_id: 0,
             interpolate_diffs: OnceCell::new(),
             z_diffs: OnceCell::new(),
+            w_diffs: OnceCell::new(),
         }
     }
 
@@ -47,15 +58,30 @@ impl<F: FSInput> Triangle<F> {
             .unwrap()
     }
 
-    pub fn get_points_as_vec3a(&self) -> &[Vec3A; 3] {
+    //TODO: Change this to a struct?
+    pub(crate) fn get_w_diffs(&self) -> &[f32; 3] {
+        self.w_diffs
+            .get_or_try_init(|| -> Result<[f32; 3], ()> {
+                let w0 = self.points[0].w();
+                let w1 = self.points[1].w();
+                let w2 = self.points[2].w();
+                let ws10 = w1 - w0;
+                let ws20 = w2 - w0;
+
+                Ok([w0, ws10, ws20])
+            })
+            .unwrap()
+    }
+
+    pub fn get_points(&self) -> &[Vec4; 3] {
         &self.points
     }
 
     pub fn get_points_as_vec2(&self) -> [Vec2; 3] {
         [
-            self.points[0].truncate(),
-            self.points[1].truncate(),
-            self.points[2].truncate(),
+            self.points[0].truncate().truncate(),
+            self.points[1].truncate().truncate(),
+            self.points[2].truncate().truncate(),
         ]
     }
 
@@ -63,27 +89,36 @@ impl<F: FSInput> Triangle<F> {
         is_backfacing_points(&self.points)
     }
 
-    pub fn transform_screen_space_orthographic<W: HasRawWindowHandle + Send + Sync>(
-        &mut self,
-        fuwa: &Fuwa<W>,
-    ) {
-        fuwa.transform_screen_space_orthographic(&mut self.points[0]);
-        fuwa.transform_screen_space_orthographic(&mut self.points[1]);
-        fuwa.transform_screen_space_orthographic(&mut self.points[2]);
+    pub fn is_front_facing(&self) -> bool {
+        self.front_facing
     }
 
-    pub fn transform_screen_space_perspective<W: HasRawWindowHandle + Send + Sync>(
-        &mut self,
-        fuwa: &Fuwa<W>,
-    ) {
+    //Reverses the winding of a back facing triangle, so
+    //the rasterizer only ever has to deal with one
+    pub(crate) fn flip_winding(&mut self) {
+        self.points.swap(1, 2);
+        self.vs_input.swap(1, 2);
+        self.barycentrics.swap(1, 2);
+        self.front_facing = !self.front_facing;
+    }
+
+    pub fn transform_screen_space_perspective<T: RenderTarget>(&mut self, fuwa: &Fuwa<T>) {
         fuwa.transform_screen_space_perspective(&mut self.points[0], &mut self.vs_input[0]);
         fuwa.transform_screen_space_perspective(&mut self.points[1], &mut self.vs_input[1]);
         fuwa.transform_screen_space_perspective(&mut self.points[2], &mut self.vs_input[2]);
+        for (barycentric, point) in self.barycentrics.iter_mut().zip(self.points.iter()) {
+            *barycentric *= point.w();
+        }
     }
 }
 
-pub(crate) fn is_backfacing_points(points: &[Vec3A; 3]) -> bool {
-    let e1 = points[1] - points[0];
-    let e2 = points[2] - points[0];
-    e1.cross(e2).dot(points[0]).is_sign_negative()
+//Sign of the determinant of the homogeneous (x, y, w) coordinates,
+//which stays valid for points behind the camera
+pub(crate) fn is_backfacing_points(points: &[Vec4; 3]) -> bool {
+    let [p0, p1, p2] = [
+        vec3a(points[0].x(), points[0].y(), points[0].w()),
+        vec3a(points[1].x(), points[1].y(), points[1].w()),
+        vec3a(points[2].x(), points[2].y(), points[2].w()),
+    ];
+    p0.dot(p1.cross(p2)).is_sign_negative()
 }
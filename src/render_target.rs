@@ -0,0 +1,64 @@
+use pixels::{Error, Pixels};
+use raw_window_handle::HasRawWindowHandle;
+use std::convert::Infallible;
+
+/// The color buffer Fuwa renders into. Frames are tightly packed RGBA8,
+/// 4 bytes per pixel, row by row. Shared with the rasterizer's worker threads.
+pub trait RenderTarget: Send + Sync {
+    type Error;
+
+    fn get_frame(&mut self) -> &mut [u8];
+
+    /// Hands the finished frame off to wherever it gets displayed.
+    fn present(&mut self) -> Result<(), Self::Error>;
+
+    /// Resizes the presentation surface. The frame itself keeps the
+    /// resolution Fuwa was created with.
+    fn resize(&mut self, width: u32, height: u32);
+}
+
+impl<W: HasRawWindowHandle + Send + Sync> RenderTarget for Pixels<W> {
+    type Error = Error;
+
+    fn get_frame(&mut self) -> &mut [u8] {
+        Pixels::get_frame(self)
+    }
+
+    fn present(&mut self) -> Result<(), Error> {
+        self.render()
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        Pixels::resize(self, width, height)
+    }
+}
+
+/// Offscreen frame stored in CPU memory, for rendering without a window.
+impl RenderTarget for Vec<u8> {
+    type Error = Infallible;
+
+    fn get_frame(&mut self) -> &mut [u8] {
+        self
+    }
+
+    fn present(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn resize(&mut self, _width: u32, _height: u32) {}
+}
+
+/// Offscreen frame backed by user supplied memory.
+impl RenderTarget for &mut [u8] {
+    type Error = Infallible;
+
+    fn get_frame(&mut self) -> &mut [u8] {
+        self
+    }
+
+    fn present(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn resize(&mut self, _width: u32, _height: u32) {}
+}
@@ -0,0 +1,494 @@
+use crate::{
+    rasterization::FragmentBuffer,
+    render_pipeline::{DepthBuffer, StencilBuffer},
+};
+use crate::{
+    AnyResource, Fuwa, Handle, RenderTarget, ScissorRect, Texture, TextureFormat, Viewport,
+};
+
+//Everything Fuwa renders into which depends on the size of the framebuffer
+pub(crate) struct FramebufferState {
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    viewport: Viewport,
+    scissor: Option<ScissorRect>,
+    depth_buffer: DepthBuffer,
+    stencil_buffer: StencilBuffer,
+    fragment_buffer: FragmentBuffer,
+}
+
+/// Offscreen attachments, which are textures in the Uniforms.
+pub struct RenderTexture {
+    pub(crate) colors: Vec<Handle<Texture>>,
+    pub(crate) color_formats: Vec<TextureFormat>,
+    depth: Option<Handle<Texture>>,
+    //While bound, holds the state of the window's framebuffer instead.
+    //Only None in the middle of swapping.
+    state: Option<FramebufferState>,
+    //Level 0 of each color texture, taken out of the Uniforms while bound
+    pub(crate) color_data: Vec<Vec<u8>>,
+    //Whether anything was drawn, rendered or cleared since it was bound
+    drawn: bool,
+}
+
+impl RenderTexture {
+    pub(crate) fn uses_texture(&self, handle: Handle<Texture>) -> bool {
+        self.colors.contains(&handle) || self.depth == Some(handle)
+    }
+}
+
+impl<T: RenderTarget> Fuwa<T> {
+    /// Creates offscreen attachments, which get added to the Uniforms as regular textures.
+    /// Bind it with set_render_texture to draw and render into it instead of the window.
+    pub fn create_render_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        color_format: Option<TextureFormat>,
+        depth: bool,
+    ) -> Handle<RenderTexture> {
+        let color_formats = color_format.as_ref().map(std::slice::from_ref);
+        self.create_render_texture_with_attachments(
+            width,
+            height,
+            color_formats.unwrap_or_default(),
+            depth,
+        )
+    }
+
+    /// Creates a render texture with a color attachment per format, like a G-buffer.
+    /// Fragment shaders write to them by returning one color per attachment, in the same order.
+    pub fn create_render_texture_with_attachments(
+        &mut self,
+        width: u32,
+        height: u32,
+        color_formats: &[TextureFormat],
+        depth: bool,
+    ) -> Handle<RenderTexture> {
+        let colors = color_formats
+            .iter()
+            .map(|&format| {
+                assert!(
+                    !format.is_depth(),
+                    "Color attachments can't use a depth format!"
+                );
+                self.add_texture(Texture::new(
+                    width,
+                    height,
+                    format,
+                    vec![0; width as usize * height as usize * format.bytes_per_texel()],
+                ))
+            })
+            .collect();
+        let depth = if depth {
+            let format = TextureFormat::Depth32Float;
+            Some(self.add_texture(Texture::new(
+                width,
+                height,
+                format,
+                vec![0; width as usize * height as usize * format.bytes_per_texel()],
+            )))
+        } else {
+            None
+        };
+
+        self.render_textures.insert(RenderTexture {
+            colors,
+            color_formats: color_formats.to_vec(),
+            depth,
+            state: Some(FramebufferState {
+                width,
+                height,
+                format: color_formats
+                    .first()
+                    .copied()
+                    .unwrap_or(TextureFormat::Rgba8Unorm),
+                viewport: Viewport::new(0., 0., width as f32, height as f32),
+                scissor: None,
+                depth_buffer: DepthBuffer::new(width, height),
+                stencil_buffer: StencilBuffer::new(width, height),
+                fragment_buffer: FragmentBuffer::new(width, height),
+            }),
+            color_data: Vec::new(),
+            drawn: false,
+        })
+    }
+
+    /// Texture handle of the first color attachment, if there is one.
+    pub fn get_render_texture_color(
+        &self,
+        handle: Handle<RenderTexture>,
+    ) -> Option<Handle<Texture>> {
+        self.get_render_texture_colors(handle)?.first().copied()
+    }
+
+    /// Texture handles of every color attachment, in the order fragment shaders write them.
+    pub fn get_render_texture_colors(
+        &self,
+        handle: Handle<RenderTexture>,
+    ) -> Option<&[Handle<Texture>]> {
+        Some(&self.render_textures.get(handle)?.colors)
+    }
+
+    /// Texture handle of the Depth32Float depth attachment, if there is one.
+    /// A depth only render texture drawn from a light's point of view can be
+    /// read back as a shadow map through a comparison sampler.
+    pub fn get_render_texture_depth(
+        &self,
+        handle: Handle<RenderTexture>,
+    ) -> Option<Handle<Texture>> {
+        self.render_textures.get(handle)?.depth
+    }
+
+    /// Makes every following draw, render and clear use the render texture's
+    /// attachments, or the window again if None. Each has its own viewport,
+    /// scissor, stencil buffer and pending fragments.
+    /// Attachments are written back to their textures, with new mipmaps, once
+    /// unbound if anything was drawn, and can't be sampled while bound.
+    pub fn set_render_texture(&mut self, handle: Option<Handle<RenderTexture>>) {
+        if self.bound_render_texture == handle {
+            return;
+        }
+        assert!(
+            handle.is_none_or(|handle| self.render_textures.contains(handle)),
+            "Invalid render texture handle!"
+        );
+
+        if let Some(bound) = self.bound_render_texture.take() {
+            self.unbind_render_texture(bound);
+        }
+
+        if let Some(handle) = handle {
+            self.swap_framebuffer_state(handle);
+            let uniforms = &mut self.uniforms;
+            let color_data = self.render_textures[handle]
+                .colors
+                .iter()
+                .map(|&color| {
+                    let texture = uniforms.get_texture_mut(color).unwrap();
+                    std::mem::take(&mut texture.levels[0].data)
+                })
+                .collect();
+            self.render_textures[handle].color_data = color_data;
+            let render_texture = &self.render_textures[handle];
+            self.uniforms.set_bound_textures(
+                render_texture
+                    .colors
+                    .iter()
+                    .chain(&render_texture.depth)
+                    .copied()
+                    .collect(),
+            );
+            self.bound_render_texture = Some(handle);
+        }
+    }
+
+    pub fn get_render_texture(&self) -> Option<Handle<RenderTexture>> {
+        self.bound_render_texture
+    }
+
+    /// Unbinds the render texture if needed, and removes it along with its attachments.
+    /// Returns false if the handle was already invalid.
+    pub fn remove_render_texture(&mut self, handle: Handle<RenderTexture>) -> bool {
+        if self.bound_render_texture == Some(handle) {
+            self.set_render_texture(None);
+        }
+
+        match self.render_textures.remove(handle) {
+            Some(render_texture) => {
+                for texture in render_texture.colors.iter().chain(&render_texture.depth) {
+                    self.uniforms.remove_texture(*texture);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    //Drops the shader's pending fragments from the window and every render texture
+    pub(crate) fn release_shader_fragments(&mut self, shader: Handle<AnyResource>) {
+        let slab_map = &self.fragment_slab_map;
+        self.fragment_buffer.release_shader(shader, slab_map);
+        for render_texture in self.render_textures.values_mut() {
+            if let Some(state) = &mut render_texture.state {
+                state.fragment_buffer.release_shader(shader, slab_map);
+            }
+        }
+    }
+
+    //Whether the window or any render texture has fragments waiting to be shaded
+    pub(crate) fn has_pending_fragments(&self) -> bool {
+        self.fragment_buffer.has_pending()
+            || self.render_textures.iter().any(|(_, render_texture)| {
+                render_texture
+                    .state
+                    .as_ref()
+                    .is_some_and(|state| state.fragment_buffer.has_pending())
+            })
+    }
+
+    //Called by everything which writes to the attachments, so unbinding
+    //only rebuilds the textures when they changed
+    pub(crate) fn mark_render_texture_drawn(&mut self) {
+        if let Some(bound) = self.bound_render_texture {
+            self.render_textures[bound].drawn = true;
+        }
+    }
+
+    fn unbind_render_texture(&mut self, handle: Handle<RenderTexture>) {
+        let render_texture = &mut self.render_textures[handle];
+        let depth = render_texture.depth;
+        let drawn = std::mem::take(&mut render_texture.drawn);
+
+        let color_data = std::mem::take(&mut render_texture.color_data);
+        for (&color, data) in render_texture.colors.iter().zip(color_data) {
+            let texture = self.uniforms.get_texture_mut(color).unwrap();
+            texture.levels[0].data = data;
+            if drawn {
+                texture.generate_mips();
+            }
+        }
+        self.uniforms.set_bound_textures(Vec::new());
+
+        if let Some(depth) = depth.filter(|_| drawn) {
+            let texture = self.uniforms.get_texture_mut(depth).unwrap();
+            let texels = (self.width * self.height) as usize;
+            texture.levels[0].data = self.depth_buffer.depth_buffer[..texels]
+                .iter()
+                .flat_map(|depth| depth.to_le_bytes().to_vec())
+                .collect();
+            texture.generate_mips();
+        }
+
+        self.swap_framebuffer_state(handle);
+    }
+
+    fn swap_framebuffer_state(&mut self, handle: Handle<RenderTexture>) {
+        let mut state = self.render_textures[handle].state.take().unwrap();
+        self.swap_framebuffer_fields(&mut state);
+        self.render_textures[handle].state = Some(state);
+    }
+
+    fn swap_framebuffer_fields(&mut self, state: &mut FramebufferState) {
+        std::mem::swap(&mut self.width, &mut state.width);
+        std::mem::swap(&mut self.height, &mut state.height);
+        std::mem::swap(&mut self.framebuffer_format, &mut state.format);
+        std::mem::swap(&mut self.viewport, &mut state.viewport);
+        std::mem::swap(&mut self.scissor, &mut state.scissor);
+        std::mem::swap(&mut self.depth_buffer, &mut state.depth_buffer);
+        std::mem::swap(&mut self.stencil_buffer, &mut state.stencil_buffer);
+        std::mem::swap(&mut self.fragment_buffer, &mut state.fragment_buffer);
+        self.pixel_count = self.width * self.height;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline, CullMode, FragmentContext, FragmentShader, IndexedVertexList};
+    use crate::{Uniforms, VSInput, VertexShader};
+    use glam::*;
+
+    #[derive(Copy, Clone)]
+    struct Vertex(Vec4);
+
+    impl VSInput for Vertex {}
+
+    struct ClipSpace;
+
+    impl VertexShader<Vertex, f32> for ClipSpace {
+        fn vertex_shader_fn(&self, vertex: &Vertex, _uniforms: &Uniforms) -> (Vec4, f32) {
+            (vertex.0, 0.)
+        }
+    }
+
+    //Red on the left half of a 4x4 target, blue on the right
+    #[derive(Clone)]
+    struct Halves;
+
+    impl FragmentShader<f32> for Halves {
+        type Output = Vec4;
+
+        fn fragment_shader_fn(
+            &self,
+            _input: f32,
+            context: &FragmentContext<f32>,
+            _uniforms: &Uniforms,
+        ) -> Option<Vec4> {
+            if context.frag_coord.x() < 2. {
+                Some(vec4(1., 0., 0., 1.))
+            } else {
+                Some(vec4(0., 0., 1., 1.))
+            }
+        }
+    }
+
+    fn texel<T: RenderTarget>(
+        fuwa: &Fuwa<T>,
+        handle: Handle<Texture>,
+        level: usize,
+        x: u32,
+        y: u32,
+    ) -> Vec4 {
+        let texture = fuwa.uniforms.bound().get_texture(handle).unwrap();
+        texture.levels[level].get_texel(x, y, 0, texture.format())
+    }
+
+    fn assert_close(actual: Vec4, expected: Vec4) {
+        assert!(
+            (actual - expected).abs().max_element() < 0.01,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn unbinding_writes_the_attachments_and_their_mips() {
+        let mut fuwa = Fuwa::new_offscreen(4, 4, 1);
+        fuwa.set_cull_mode(CullMode::None);
+        let handle = fuwa.create_render_texture(4, 4, Some(TextureFormat::Rgba8Unorm), true);
+        let color = fuwa.get_render_texture_color(handle).unwrap();
+        let depth = fuwa.get_render_texture_depth(handle).unwrap();
+        let shader = fuwa.add_fragment_shader(Halves);
+
+        fuwa.set_render_texture(Some(handle));
+        let vertices = [
+            Vertex(vec4(-1., -1., 0.25, 1.)),
+            Vertex(vec4(3., -1., 0.25, 1.)),
+            Vertex(vec4(-1., 3., 0.25, 1.)),
+        ];
+        let list = IndexedVertexList {
+            raw_vertex_list: &vertices,
+            index_list: &[0, 1, 2],
+        };
+        pipeline::draw(&mut fuwa, &ClipSpace, shader, &list);
+        fuwa.render(shader);
+        //Goes to the attachment as well
+        fuwa.set_pixel(3, 3, &[0, 255, 0, 255]);
+        fuwa.set_render_texture(None);
+
+        let (red, green, blue) = (
+            vec4(1., 0., 0., 1.),
+            vec4(0., 1., 0., 1.),
+            vec4(0., 0., 1., 1.),
+        );
+        for y in 0..4 {
+            for x in 0..4 {
+                let expected = match (x, y) {
+                    (3, 3) => green,
+                    (0..=1, _) => red,
+                    _ => blue,
+                };
+                assert_close(texel(&fuwa, color, 0, x, y), expected);
+                assert_close(texel(&fuwa, depth, 0, x, y), vec4(0.25, 0., 0., 1.));
+            }
+        }
+        assert_close(texel(&fuwa, color, 1, 0, 1), red);
+        assert_close(texel(&fuwa, color, 1, 1, 1), vec4(0., 0.25, 0.75, 1.));
+        assert_close(
+            texel(&fuwa, color, 2, 0, 0),
+            vec4(0.5, 1. / 16., 7. / 16., 1.),
+        );
+        assert_close(texel(&fuwa, depth, 2, 0, 0), vec4(0.25, 0., 0., 1.));
+
+        //The window never got drawn to
+        assert!(fuwa.get_frame().iter().all(|&byte| byte == 0));
+    }
+
+    //Red to the first attachment, and values past the unorm range to the second
+    #[derive(Clone)]
+    struct Split;
+
+    impl FragmentShader<f32> for Split {
+        type Output = (Vec4, Vec4);
+
+        fn fragment_shader_fn(
+            &self,
+            _input: f32,
+            _context: &FragmentContext<f32>,
+            _uniforms: &Uniforms,
+        ) -> Option<(Vec4, Vec4)> {
+            Some((vec4(1., 0., 0., 1.), vec4(2., 0.5, -1., 1.)))
+        }
+    }
+
+    #[test]
+    fn fragment_outputs_go_to_their_own_attachments() {
+        let mut fuwa = Fuwa::new_offscreen(4, 4, 1);
+        fuwa.set_cull_mode(CullMode::None);
+        let handle = fuwa.create_render_texture_with_attachments(
+            4,
+            4,
+            &[TextureFormat::Rgba8Unorm, TextureFormat::Rgba32Float],
+            false,
+        );
+        let colors = fuwa.get_render_texture_colors(handle).unwrap().to_vec();
+        let split = fuwa.add_fragment_shader(Split);
+        let halves = fuwa.add_fragment_shader(Halves);
+        let vertices = [
+            Vertex(vec4(-1., -1., 0.25, 1.)),
+            Vertex(vec4(3., -1., 0.25, 1.)),
+            Vertex(vec4(-1., 3., 0.25, 1.)),
+        ];
+        let list = IndexedVertexList {
+            raw_vertex_list: &vertices,
+            index_list: &[0, 1, 2],
+        };
+
+        fuwa.set_render_texture(Some(handle));
+        pipeline::draw(&mut fuwa, &ClipSpace, split, &list);
+        fuwa.render(split);
+        fuwa.set_render_texture(None);
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_close(texel(&fuwa, colors[0], 0, x, y), vec4(1., 0., 0., 1.));
+                assert_close(texel(&fuwa, colors[1], 0, x, y), vec4(2., 0.5, -1., 1.));
+            }
+        }
+
+        //A single color only writes the first attachment, drawn closer to pass the depth test
+        let closer: Vec<_> = vertices
+            .iter()
+            .map(|vertex| Vertex(vertex.0 * vec4(1., 1., 0.5, 1.)))
+            .collect();
+        let list = IndexedVertexList {
+            raw_vertex_list: &closer,
+            index_list: &[0, 1, 2],
+        };
+        fuwa.set_render_texture(Some(handle));
+        pipeline::draw(&mut fuwa, &ClipSpace, halves, &list);
+        fuwa.render(halves);
+        fuwa.set_render_texture(None);
+        for y in 0..4 {
+            for x in 0..4 {
+                let expected = if x < 2 {
+                    vec4(1., 0., 0., 1.)
+                } else {
+                    vec4(0., 0., 1., 1.)
+                };
+                assert_close(texel(&fuwa, colors[0], 0, x, y), expected);
+                assert_close(texel(&fuwa, colors[1], 0, x, y), vec4(2., 0.5, -1., 1.));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "render texture is bound")]
+    fn framebuffer_format_cant_change_while_bound() {
+        let mut fuwa = Fuwa::new_offscreen(4, 4, 1);
+        let handle = fuwa.create_render_texture(4, 4, Some(TextureFormat::Rgba8Unorm), false);
+        fuwa.set_render_texture(Some(handle));
+        fuwa.set_framebuffer_format(TextureFormat::Rgba8UnormSrgb);
+    }
+
+    #[test]
+    #[should_panic(expected = "render texture is bound")]
+    fn depth_buffer_cant_be_rendered_while_bound() {
+        let mut fuwa = Fuwa::new_offscreen(4, 4, 1);
+        let handle = fuwa.create_render_texture(4, 4, None, true);
+        fuwa.set_render_texture(Some(handle));
+        let _ = fuwa.render_depth_buffer();
+    }
+}
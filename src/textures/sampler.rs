@@ -1,2 +1,764 @@
-//TODO: This
-pub struct Sampler {}
+use crate::{CompareFunction, MipLevel, Texture};
+use glam::*;
+use std::convert::TryFrom;
+
+/// How texture coordinates outside of [0, 1] are resolved.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddressMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    /// Texels outside of the texture take the sampler's border color.
+    ClampToBorder,
+}
+
+impl AddressMode {
+    //Maps a texel coordinate into the texture, or None if it hits the border
+    fn apply(self, coord: i32, size: u32) -> Option<u32> {
+        //Textures are never empty, but a zero size would divide by zero below
+        let size = i32::try_from(size).ok().filter(|&size| size > 0)?;
+        match self {
+            Self::Repeat => Some(coord.rem_euclid(size) as u32),
+            Self::MirroredRepeat => {
+                let coord = coord.rem_euclid(size * 2);
+                if coord >= size {
+                    Some((size * 2 - 1 - coord) as u32)
+                } else {
+                    Some(coord as u32)
+                }
+            }
+            Self::ClampToEdge => Some(coord.clamp(0, size - 1) as u32),
+            Self::ClampToBorder => {
+                if coord >= 0 && coord < size {
+                    Some(coord as u32)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FilterMode {
+    Nearest,
+    /// Bilinear filtering between the four closest texels,
+    /// or the eight closest for 3D textures.
+    Linear,
+}
+
+/// Describes how a texture is read from a fragment shader.
+/// Added to the Uniforms like textures, so any shader can look it up by handle.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sampler {
+    pub address_mode_u: AddressMode,
+    pub address_mode_v: AddressMode,
+    /// Only used by 3D textures.
+    pub address_mode_w: AddressMode,
+    pub mag_filter: FilterMode,
+    pub min_filter: FilterMode,
+    /// Linear blends between the two closest mip levels, giving trilinear
+    /// filtering along with a linear min filter.
+    pub mipmap_filter: FilterMode,
+    /// Maximum number of probes taken along the major axis of the pixel's
+    /// footprint when sampling with derivatives. 1 disables anisotropic filtering.
+    pub max_anisotropy: u8,
+    /// Normalized RGBA, returned as is for any format.
+    /// Comparisons use the red channel as the border's depth.
+    pub border_color: [f32; 4],
+    /// Makes this a comparison sampler for sample_compare, which tests
+    /// `reference <op> texel` instead of returning the texel.
+    pub compare: Option<CompareFunction>,
+}
+
+impl Sampler {
+    /// Clamps to the edge and uses nearest filtering.
+    pub const DEFAULT: Self = Self {
+        address_mode_u: AddressMode::ClampToEdge,
+        address_mode_v: AddressMode::ClampToEdge,
+        address_mode_w: AddressMode::ClampToEdge,
+        mag_filter: FilterMode::Nearest,
+        min_filter: FilterMode::Nearest,
+        mipmap_filter: FilterMode::Nearest,
+        max_anisotropy: 1,
+        border_color: [0., 0., 0., 0.],
+        compare: None,
+    };
+
+    pub fn new(address_mode: AddressMode, filter: FilterMode) -> Self {
+        Self {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            ..Self::DEFAULT
+        }
+    }
+
+    /// Comparison sampler for shadow maps. Anything outside of the map gets
+    /// compared against a depth of 1, and a linear filter gives 2x2 PCF.
+    pub fn new_compare(compare: CompareFunction, filter: FilterMode) -> Self {
+        Self {
+            mag_filter: filter,
+            min_filter: filter,
+            border_color: [1., 1., 1., 1.],
+            compare: Some(compare),
+            ..Self::new(AddressMode::ClampToBorder, FilterMode::Nearest)
+        }
+    }
+
+    /// Samples the top mip level of the texture as if it were magnified.
+    pub fn sample(&self, texture: &Texture, uv: Vec2) -> Vec4 {
+        self.sample_level(texture, uv, 0.)
+    }
+
+    /// Samples the texture with the level of detail picked from the screen space
+    /// derivatives of the texture coordinates.
+    pub fn sample_grad(&self, texture: &Texture, uv: Vec2, ddx: Vec2, ddy: Vec2) -> Vec4 {
+        self.sample_layer_grad(texture, 0, uv, ddx, ddy)
+    }
+
+    /// Samples the texture with the min filter when the level of detail is
+    /// above 0, meaning the texture is minified, or the mag filter otherwise.
+    pub fn sample_level(&self, texture: &Texture, uv: Vec2, lod: f32) -> Vec4 {
+        self.sample_layer_level(texture, 0, uv, lod)
+    }
+
+    /// Samples one layer of a texture array. Out of range layers are clamped.
+    pub fn sample_array(&self, texture: &Texture, uv: Vec2, layer: u32) -> Vec4 {
+        self.sample_array_level(texture, uv, layer, 0.)
+    }
+
+    pub fn sample_array_grad(
+        &self,
+        texture: &Texture,
+        uv: Vec2,
+        layer: u32,
+        ddx: Vec2,
+        ddy: Vec2,
+    ) -> Vec4 {
+        self.sample_layer_grad(texture, layer.min(texture.layer_count() - 1), uv, ddx, ddy)
+    }
+
+    pub fn sample_array_level(&self, texture: &Texture, uv: Vec2, layer: u32, lod: f32) -> Vec4 {
+        self.sample_layer_level(texture, layer.min(texture.layer_count() - 1), uv, lod)
+    }
+
+    /// Samples a cubemap in the given direction, which doesn't need to be normalized.
+    /// Bilinear filtering reads across the edges of neighbouring faces, so there are
+    /// no seams, and the address modes are ignored.
+    pub fn sample_cube(&self, texture: &Texture, direction: Vec3) -> Vec4 {
+        self.sample_cube_level(texture, direction, 0.)
+    }
+
+    /// Picks the level of detail from the screen space derivatives of the direction.
+    /// Cubemaps are always filtered isotropically.
+    pub fn sample_cube_grad(
+        &self,
+        texture: &Texture,
+        direction: Vec3,
+        ddx: Vec3,
+        ddy: Vec3,
+    ) -> Vec4 {
+        //The derivatives get projected onto the face, which spans 2 units
+        let major_axis = direction.abs().max_element();
+        let texels = ddx.length().max(ddy.length()) / major_axis * texture.width() as f32 * 0.5;
+        self.sample_cube_level(texture, direction, texels.log2())
+    }
+
+    pub fn sample_cube_level(&self, texture: &Texture, direction: Vec3, lod: f32) -> Vec4 {
+        self.filter_mips(texture, lod, |level, filter| {
+            self.sample_cube_mip(texture, level, direction, filter)
+        })
+    }
+
+    /// Samples a 3D texture with trilinear filtering when the filters are linear.
+    pub fn sample_3d(&self, texture: &Texture, uvw: Vec3) -> Vec4 {
+        self.sample_3d_level(texture, uvw, 0.)
+    }
+
+    pub fn sample_3d_grad(&self, texture: &Texture, uvw: Vec3, ddx: Vec3, ddy: Vec3) -> Vec4 {
+        let size = vec3(
+            texture.width() as f32,
+            texture.height() as f32,
+            texture.depth() as f32,
+        );
+        let length_x = (ddx * size).length_squared();
+        let length_y = (ddy * size).length_squared();
+        self.sample_3d_level(texture, uvw, 0.5 * length_x.max(length_y).log2())
+    }
+
+    pub fn sample_3d_level(&self, texture: &Texture, uvw: Vec3, lod: f32) -> Vec4 {
+        self.filter_mips(texture, lod, |level, filter| {
+            self.sample_3d_mip(texture, level, uvw, filter)
+        })
+    }
+
+    /// Compares the reference depth against the top mip level of a depth texture,
+    /// returning 1 where it passes and 0 where it fails. With a linear mag filter
+    /// the four closest texels are compared on their own and the results
+    /// filtered bilinearly, giving soft shadow edges (percentage closer filtering).
+    pub fn sample_compare(&self, texture: &Texture, uv: Vec2, reference: f32) -> f32 {
+        let compare = self
+            .compare
+            .expect("sample_compare needs a sampler with a compare function!");
+        let level = &texture.levels[0];
+
+        bilinear_or_nearest(
+            uv * vec2(level.width as f32, level.height as f32),
+            self.mag_filter,
+            |x, y| {
+                let depth = self.fetch(texture, level, 0, x, y).x();
+                Vec4::splat(compare.compare(reference, depth) as u8 as f32)
+            },
+        )
+        .x()
+    }
+
+    fn sample_layer_grad(
+        &self,
+        texture: &Texture,
+        layer: u32,
+        uv: Vec2,
+        ddx: Vec2,
+        ddy: Vec2,
+    ) -> Vec4 {
+        if self.max_anisotropy <= 1 {
+            return self.sample_layer_level(texture, layer, uv, calculate_lod(texture, ddx, ddy));
+        }
+
+        //Lengths of the footprint's axes in texels
+        let size = vec2(texture.width() as f32, texture.height() as f32);
+        let length_x = (ddx * size).length();
+        let length_y = (ddy * size).length();
+        let (major_axis, major_length, minor_length) = if length_x > length_y {
+            (ddx, length_x, length_y)
+        } else {
+            (ddy, length_y, length_x)
+        };
+
+        let probes = (major_length / minor_length)
+            .ceil()
+            .clamp(1., self.max_anisotropy as f32);
+        if probes.is_nan() || probes <= 1. {
+            return self.sample_layer_level(texture, layer, uv, calculate_lod(texture, ddx, ddy));
+        }
+
+        //Each probe only has to cover its share of the major axis
+        let lod = (major_length / probes).log2();
+        let probe_count = probes as u32;
+        let color = (0..probe_count).fold(Vec4::zero(), |sum, probe| {
+            let offset = (probe as f32 + 0.5) / probes - 0.5;
+            sum + self.sample_layer_level(texture, layer, uv + major_axis * offset, lod)
+        });
+
+        color / probes
+    }
+
+    fn sample_layer_level(&self, texture: &Texture, layer: u32, uv: Vec2, lod: f32) -> Vec4 {
+        self.filter_mips(texture, lod, |level, filter| {
+            self.sample_mip(texture, level, layer, uv, filter)
+        })
+    }
+
+    //Picks the filter and mip level(s) for the level of detail, and blends
+    //between the samples of each level when needed
+    fn filter_mips(
+        &self,
+        texture: &Texture,
+        lod: f32,
+        sample_mip: impl Fn(usize, FilterMode) -> Vec4,
+    ) -> Vec4 {
+        if lod <= 0. {
+            return sample_mip(0, self.mag_filter);
+        }
+
+        let max_level = (texture.levels.len() - 1) as f32;
+        let lod = lod.min(max_level);
+
+        match self.mipmap_filter {
+            FilterMode::Nearest => sample_mip(lod.round() as usize, self.min_filter),
+            FilterMode::Linear => {
+                let level = lod.floor();
+                let near = sample_mip(level as usize, self.min_filter);
+                if level == max_level {
+                    return near;
+                }
+                let far = sample_mip(level as usize + 1, self.min_filter);
+                near.lerp(far, lod - level)
+            }
+        }
+    }
+
+    fn sample_mip(
+        &self,
+        texture: &Texture,
+        level: usize,
+        layer: u32,
+        uv: Vec2,
+        filter: FilterMode,
+    ) -> Vec4 {
+        let level = &texture.levels[level];
+        let fetch = |x, y| self.fetch(texture, level, layer, x, y);
+        bilinear_or_nearest(
+            uv * vec2(level.width as f32, level.height as f32),
+            filter,
+            fetch,
+        )
+    }
+
+    fn sample_3d_mip(
+        &self,
+        texture: &Texture,
+        level: usize,
+        uvw: Vec3,
+        filter: FilterMode,
+    ) -> Vec4 {
+        let level = &texture.levels[level];
+        let texel = uvw * vec3(level.width as f32, level.height as f32, level.depth as f32);
+
+        match filter {
+            FilterMode::Nearest => self.fetch_3d(
+                texture,
+                level,
+                texel.x().floor() as i32,
+                texel.y().floor() as i32,
+                texel.z().floor() as i32,
+            ),
+            FilterMode::Linear => {
+                //Two bilinear samples from the closest slices
+                let z = texel.z() - 0.5;
+                let z0 = z.floor();
+                let front = bilinear_or_nearest(texel.truncate(), filter, |x, y| {
+                    self.fetch_3d(texture, level, x, y, z0 as i32)
+                });
+                let back = bilinear_or_nearest(texel.truncate(), filter, |x, y| {
+                    self.fetch_3d(texture, level, x, y, z0 as i32 + 1)
+                });
+                front.lerp(back, z - z0)
+            }
+        }
+    }
+
+    fn sample_cube_mip(
+        &self,
+        texture: &Texture,
+        level: usize,
+        direction: Vec3,
+        filter: FilterMode,
+    ) -> Vec4 {
+        let level = &texture.levels[level];
+        let size = level.width;
+        let (face, uv) = cube_face_uv(direction);
+
+        bilinear_or_nearest(uv * size as f32, filter, |x, y| {
+            //Texels past the edge get looked up on the neighbouring face
+            //by turning their center back into a direction
+            let (face, x, y) = if x < 0 || y < 0 || x >= size as i32 || y >= size as i32 {
+                let st =
+                    (vec2(x as f32, y as f32) + Vec2::splat(0.5)) / size as f32 * 2. - Vec2::one();
+                let (face, uv) = cube_face_uv(cube_face_direction(face, st));
+                let texel = uv * size as f32;
+                (face, texel.x() as i32, texel.y() as i32)
+            } else {
+                (face, x, y)
+            };
+
+            level.get_texel(
+                (x.max(0) as u32).min(size - 1),
+                (y.max(0) as u32).min(size - 1),
+                face,
+                texture.format,
+            )
+        })
+    }
+
+    //Reads a single texel after applying the address modes
+    fn fetch(&self, texture: &Texture, level: &MipLevel, layer: u32, x: i32, y: i32) -> Vec4 {
+        match (
+            self.address_mode_u.apply(x, level.width),
+            self.address_mode_v.apply(y, level.height),
+        ) {
+            (Some(x), Some(y)) => level.get_texel(x, y, layer, texture.format),
+            _ => Vec4::from(self.border_color),
+        }
+    }
+
+    fn fetch_3d(&self, texture: &Texture, level: &MipLevel, x: i32, y: i32, z: i32) -> Vec4 {
+        match self.address_mode_w.apply(z, level.depth) {
+            Some(z) => self.fetch(texture, level, z, x, y),
+            None => Vec4::from(self.border_color),
+        }
+    }
+}
+
+//Takes a position in texels, and reads the texels around it through fetch
+fn bilinear_or_nearest(texel: Vec2, filter: FilterMode, fetch: impl Fn(i32, i32) -> Vec4) -> Vec4 {
+    match filter {
+        FilterMode::Nearest => fetch(texel.x().floor() as i32, texel.y().floor() as i32),
+        FilterMode::Linear => {
+            //Texel centers are at half coordinates
+            let texel = texel - Vec2::splat(0.5);
+            let origin = texel.floor();
+            let weights = texel - origin;
+            let (x0, y0) = (origin.x() as i32, origin.y() as i32);
+
+            let top = fetch(x0, y0).lerp(fetch(x0 + 1, y0), weights.x());
+            let bottom = fetch(x0, y0 + 1).lerp(fetch(x0 + 1, y0 + 1), weights.x());
+            top.lerp(bottom, weights.y())
+        }
+    }
+}
+
+//Picks the face from the major axis of the direction, and where on it the direction lands
+fn cube_face_uv(direction: Vec3) -> (u32, Vec2) {
+    let (x, y, z) = (direction.x(), direction.y(), direction.z());
+    let abs = direction.abs();
+
+    let (face, major_axis, s, t) = if abs.x() >= abs.y() && abs.x() >= abs.z() {
+        if x >= 0. {
+            (0, abs.x(), -z, -y)
+        } else {
+            (1, abs.x(), z, -y)
+        }
+    } else if abs.y() >= abs.z() {
+        if y >= 0. {
+            (2, abs.y(), x, z)
+        } else {
+            (3, abs.y(), x, -z)
+        }
+    } else if z >= 0. {
+        (4, abs.z(), x, -y)
+    } else {
+        (5, abs.z(), -x, -y)
+    };
+
+    (face, (vec2(s, t) / major_axis + Vec2::one()) * 0.5)
+}
+
+//Inverse of cube_face_uv, with s and t from -1 to 1 across the face
+fn cube_face_direction(face: u32, st: Vec2) -> Vec3 {
+    let (s, t) = (st.x(), st.y());
+    match face {
+        0 => vec3(1., -t, -s),
+        1 => vec3(-1., -t, s),
+        2 => vec3(s, 1., t),
+        3 => vec3(s, -1., -t),
+        4 => vec3(s, -t, 1.),
+        _ => vec3(-s, -t, -1.),
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+//log2 of the longest side of the pixel's footprint, in texels
+fn calculate_lod(texture: &Texture, ddx: Vec2, ddy: Vec2) -> f32 {
+    let size = vec2(texture.width() as f32, texture.height() as f32);
+    let length_x = (ddx * size).length_squared();
+    let length_y = (ddy * size).length_squared();
+    0.5 * length_x.max(length_y).log2()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TextureFormat;
+
+    fn apply_all(mode: AddressMode) -> Vec<Option<u32>> {
+        (-5..9).map(|coord| mode.apply(coord, 4)).collect()
+    }
+
+    #[test]
+    fn repeat_wraps_both_ways() {
+        let expected = [3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0];
+        assert_eq!(
+            apply_all(AddressMode::Repeat),
+            expected.iter().map(|&x| Some(x)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn mirrored_repeat_reflects_at_the_edges() {
+        let expected = [3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0];
+        assert_eq!(
+            apply_all(AddressMode::MirroredRepeat),
+            expected.iter().map(|&x| Some(x)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn clamp_to_edge_stays_on_the_last_texel() {
+        let expected = [0, 0, 0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3, 3];
+        assert_eq!(
+            apply_all(AddressMode::ClampToEdge),
+            expected.iter().map(|&x| Some(x)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn clamp_to_border_misses_outside() {
+        let applied = apply_all(AddressMode::ClampToBorder);
+        assert_eq!(&applied[5..9], &[Some(0), Some(1), Some(2), Some(3)]);
+        assert!(applied[..5]
+            .iter()
+            .chain(&applied[9..])
+            .all(Option::is_none));
+    }
+
+    #[test]
+    fn zero_sizes_never_map() {
+        for mode in [
+            AddressMode::Repeat,
+            AddressMode::MirroredRepeat,
+            AddressMode::ClampToEdge,
+            AddressMode::ClampToBorder,
+        ]
+        .iter()
+        {
+            assert_eq!(mode.apply(0, 0), None);
+        }
+    }
+
+    #[test]
+    fn cube_axes_hit_the_middle_of_their_face() {
+        let axes = [
+            Vec3::unit_x(),
+            -Vec3::unit_x(),
+            Vec3::unit_y(),
+            -Vec3::unit_y(),
+            Vec3::unit_z(),
+            -Vec3::unit_z(),
+        ];
+        for (face, &axis) in axes.iter().enumerate() {
+            assert_eq!(cube_face_uv(axis * 3.), (face as u32, vec2(0.5, 0.5)));
+        }
+    }
+
+    #[test]
+    fn cube_faces_are_oriented_like_other_apis() {
+        //The top left of +X is towards +Y and +Z, and of +Z towards -X and +Y
+        assert_eq!(cube_face_uv(vec3(1., 0.5, 0.5)), (0, vec2(0.25, 0.25)));
+        assert_eq!(cube_face_uv(vec3(-0.5, 0.5, 1.)), (4, vec2(0.25, 0.25)));
+    }
+
+    #[test]
+    fn cube_face_direction_is_the_inverse() {
+        for face in 0..6 {
+            for &st in [vec2(-0.5, 0.25), vec2(0.75, -0.9), vec2(0., 0.)].iter() {
+                let (hit, uv) = cube_face_uv(cube_face_direction(face, st));
+                assert_eq!(hit, face);
+                assert!((uv * 2. - Vec2::one() - st).abs().max_element() < 1e-6);
+            }
+        }
+    }
+
+    //8x8 with each texel of the first level holding its column,
+    //and every level below it filled with ten times its index
+    fn mip_texture() -> Texture {
+        let format = TextureFormat::R32Float;
+        let columns = (0..64).flat_map(|index| ((index % 8) as f32).to_le_bytes().to_vec());
+        let mut texture = Texture::new(8, 8, format, columns.collect());
+        for (index, level) in texture.levels.iter_mut().enumerate().skip(1) {
+            let value = (index * 10) as f32;
+            for texel in level.data.chunks_exact_mut(4) {
+                texel.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+        texture
+    }
+
+    fn assert_sampled(actual: Vec4, expected: f32) {
+        assert!(
+            (actual.x() - expected).abs() < 1e-4,
+            "{} != {}",
+            actual.x(),
+            expected
+        );
+    }
+
+    #[test]
+    fn derivatives_pick_the_mip_level() {
+        let texture = mip_texture();
+        let sampler = Sampler::DEFAULT;
+        let uv = Vec2::splat(0.5);
+
+        //Footprints of 1, 2, 4 and 8 texels, and far past the last level
+        let levels = [(1., 4.), (2., 10.), (4., 20.), (8., 30.), (64., 30.)];
+        for &(texels, expected) in levels.iter() {
+            let ddx = vec2(texels / 8., 0.);
+            let ddy = vec2(0., 1. / 8.);
+            assert_sampled(sampler.sample_grad(&texture, uv, ddx, ddy), expected);
+            assert_sampled(sampler.sample_grad(&texture, uv, ddy, ddx), expected);
+        }
+
+        //Nearest mip filtering rounds, and magnification stays on the first level
+        assert_sampled(sampler.sample_level(&texture, uv, 1.4), 10.);
+        assert_sampled(sampler.sample_level(&texture, uv, 1.6), 20.);
+        assert_sampled(sampler.sample_level(&texture, uv, -2.), 4.);
+    }
+
+    #[test]
+    fn linear_mip_filtering_blends_between_levels() {
+        let texture = mip_texture();
+        let sampler = Sampler::new(AddressMode::ClampToEdge, FilterMode::Linear);
+        let uv = Vec2::splat(0.5);
+
+        assert_sampled(sampler.sample_level(&texture, uv, 1.25), 12.5);
+        assert_sampled(sampler.sample_level(&texture, uv, 2.5), 25.);
+        assert_sampled(sampler.sample_level(&texture, uv, 5.), 30.);
+        //Halfway between the first two levels, where the first
+        //gives 3.5 from the middle of columns 3 and 4
+        assert_sampled(sampler.sample_level(&texture, uv, 0.5), 6.75);
+
+        let footprint = 2f32.powf(1.5) / 8.;
+        let ddx = vec2(footprint, 0.);
+        let ddy = vec2(0., footprint);
+        assert_sampled(sampler.sample_grad(&texture, uv, ddx, ddy), 15.);
+    }
+
+    #[test]
+    fn anisotropic_sampling_probes_along_the_major_axis() {
+        let texture = mip_texture();
+        let uv = Vec2::splat(0.5);
+        //4 texels wide and 1 tall, which isotropic filtering blurs down to level 2
+        let ddx = vec2(4. / 8., 0.);
+        let ddy = vec2(0., 1. / 8.);
+        let sampler = |max_anisotropy| Sampler {
+            max_anisotropy,
+            ..Sampler::DEFAULT
+        };
+
+        assert_sampled(sampler(1).sample_grad(&texture, uv, ddx, ddy), 20.);
+        //Four probes on the first level, hitting columns 2 to 5
+        assert_sampled(sampler(16).sample_grad(&texture, uv, ddx, ddy), 3.5);
+        //Two probes, each covering two texels on level 1
+        assert_sampled(sampler(2).sample_grad(&texture, uv, ddx, ddy), 10.);
+
+        //The footprint is turned along v, so probes stay in column 4
+        let (tall_ddx, tall_ddy) = (vec2(0., 4. / 8.), vec2(1. / 8., 0.));
+        assert_sampled(
+            sampler(16).sample_grad(&texture, uv, tall_ddx, tall_ddy),
+            4.,
+        );
+        //Square footprints need no more than one probe
+        assert_sampled(
+            sampler(16).sample_grad(&texture, uv, ddx, vec2(0., 4. / 8.)),
+            20.,
+        );
+    }
+
+    #[test]
+    fn compare_sampling_filters_the_results_of_each_texel() {
+        let depths = [0.25f32, 0.5, 0.5, 0.75];
+        let data = depths.iter().flat_map(|depth| depth.to_le_bytes().to_vec());
+        let texture = Texture::new(2, 2, TextureFormat::Depth32Float, data.collect());
+        let centers = [
+            vec2(0.25, 0.25),
+            vec2(0.75, 0.25),
+            vec2(0.25, 0.75),
+            vec2(0.75, 0.75),
+        ];
+
+        //What passes against a reference of 0.5 at each texel, and in the middle of all four
+        let functions = [
+            (CompareFunction::Never, [0., 0., 0., 0.], 0.),
+            (CompareFunction::Less, [0., 0., 0., 1.], 0.25),
+            (CompareFunction::LessEqual, [0., 1., 1., 1.], 0.75),
+            (CompareFunction::Equal, [0., 1., 1., 0.], 0.5),
+            (CompareFunction::Greater, [1., 0., 0., 0.], 0.25),
+            (CompareFunction::GreaterEqual, [1., 1., 1., 0.], 0.75),
+            (CompareFunction::NotEqual, [1., 0., 0., 1.], 0.5),
+            (CompareFunction::Always, [1., 1., 1., 1.], 1.),
+        ];
+        for &(compare, texels, middle) in functions.iter() {
+            let nearest = Sampler::new_compare(compare, FilterMode::Nearest);
+            let linear = Sampler::new_compare(compare, FilterMode::Linear);
+            for (&uv, &expected) in centers.iter().zip(&texels) {
+                assert_eq!(nearest.sample_compare(&texture, uv, 0.5), expected);
+                assert_eq!(linear.sample_compare(&texture, uv, 0.5), expected);
+            }
+            let filtered = linear.sample_compare(&texture, Vec2::splat(0.5), 0.5);
+            assert!((filtered - middle).abs() < 1e-6, "{:?}", compare);
+        }
+
+        //Half of the way into the border, which counts as a depth of 1
+        let less = Sampler::new_compare(CompareFunction::Less, FilterMode::Linear);
+        assert!((less.sample_compare(&texture, vec2(1., 0.5), 0.5) - 0.75).abs() < 1e-6);
+        assert_eq!(less.sample_compare(&texture, vec2(2., 2.), 0.5), 1.);
+    }
+
+    //2x2x2 with each texel holding x + 2y + 4z
+    fn volume() -> Texture {
+        let texels = (0..8).flat_map(|index| (index as f32).to_le_bytes().to_vec());
+        Texture::new_3d(2, 2, 2, TextureFormat::R32Float, texels.collect())
+    }
+
+    #[test]
+    fn volumes_are_filtered_trilinearly() {
+        let texture = volume();
+        let nearest = Sampler::DEFAULT;
+        let linear = Sampler::new(AddressMode::ClampToEdge, FilterMode::Linear);
+
+        for index in 0..8 {
+            let texel = vec3(
+                (index % 2) as f32,
+                (index / 2 % 2) as f32,
+                (index / 4) as f32,
+            );
+            let uvw = (texel + Vec3::splat(0.5)) / 2.;
+            assert_sampled(nearest.sample_3d(&texture, uvw), index as f32);
+            assert_sampled(linear.sample_3d(&texture, uvw), index as f32);
+        }
+
+        //Between every texel, between two slices, and a quarter of the way along each axis
+        assert_sampled(linear.sample_3d(&texture, Vec3::splat(0.5)), 3.5);
+        assert_sampled(linear.sample_3d(&texture, vec3(0.25, 0.25, 0.5)), 2.);
+        assert_sampled(linear.sample_3d(&texture, vec3(0.375, 0.375, 0.375)), 1.75);
+    }
+
+    #[test]
+    fn volume_mips_halve_the_depth_too() {
+        let texture = volume();
+        assert_eq!(texture.mip_level_count(), 2);
+        let last = &texture.levels[1];
+        assert_eq!((last.width, last.height, last.depth), (1, 1, 1));
+
+        let sampler = Sampler::DEFAULT;
+        assert_sampled(
+            sampler.sample_3d_level(&texture, vec3(0.1, 0.1, 0.1), 1.),
+            3.5,
+        );
+        let ddx = vec3(1., 0., 0.);
+        let ddy = vec3(0., 0., 1.);
+        assert_sampled(
+            sampler.sample_3d_grad(&texture, vec3(0.1, 0.1, 0.1), ddx, ddy),
+            3.5,
+        );
+
+        //Unlike the layers of an array
+        let texels = (0..8).flat_map(|index| (index as f32).to_le_bytes().to_vec());
+        let array = Texture::new_array(2, 2, 2, TextureFormat::R32Float, texels.collect());
+        assert_eq!(array.levels[1].depth, 2);
+        assert_sampled(sampler.sample_array_level(&array, Vec2::zero(), 1, 1.), 5.5);
+    }
+
+    #[test]
+    fn volumes_address_w_on_its_own() {
+        let texture = volume();
+        let sampler = Sampler {
+            address_mode_w: AddressMode::ClampToBorder,
+            border_color: [9., 9., 9., 9.],
+            ..Sampler::new(AddressMode::Repeat, FilterMode::Nearest)
+        };
+
+        assert_sampled(sampler.sample_3d(&texture, vec3(1.25, -0.75, 0.75)), 4.);
+        assert_sampled(sampler.sample_3d(&texture, vec3(0.25, 0.25, 1.25)), 9.);
+        assert_sampled(sampler.sample_3d(&texture, vec3(0.25, 0.25, -0.25)), 9.);
+    }
+}
@@ -1,11 +1,588 @@
-// pub enum TextureFormat {
-//     ARGB,
-//     RGB,
-//     RGBA,
-// }
+use crate::{FuwaError, Handle};
+use glam::*;
+use half::f16;
+
+/// Index of a texture in the Uniforms.
+pub type TextureHandle = Handle<Texture>;
+
+/// How texels are laid out in memory. Every format is read back as a
+/// normalized float Vec4, with missing channels filled in from (0, 0, 0, 1).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    R8Unorm,
+    Rg8Unorm,
+    Rgba8Unorm,
+    /// Color channels are sRGB encoded and get converted to linear when sampled.
+    Rgba8UnormSrgb,
+    R16Float,
+    Rgba16Float,
+    R32Float,
+    Rgba32Float,
+    Depth16Unorm,
+    Depth32Float,
+}
+
+impl TextureFormat {
+    pub fn bytes_per_texel(self) -> usize {
+        match self {
+            Self::R8Unorm => 1,
+            Self::Rg8Unorm | Self::R16Float | Self::Depth16Unorm => 2,
+            Self::Rgba8Unorm | Self::Rgba8UnormSrgb | Self::R32Float | Self::Depth32Float => 4,
+            Self::Rgba16Float => 8,
+            Self::Rgba32Float => 16,
+        }
+    }
+
+    pub fn is_depth(self) -> bool {
+        matches!(self, Self::Depth16Unorm | Self::Depth32Float)
+    }
+
+    pub(crate) fn decode(self, texel: &[u8]) -> Vec4 {
+        let unorm8 = |idx: usize| texel[idx] as f32 / 255.;
+        let float16 =
+            |idx: usize| f16::from_le_bytes([texel[idx * 2], texel[idx * 2 + 1]]).to_f32();
+        let float32 = |idx: usize| {
+            f32::from_le_bytes([
+                texel[idx * 4],
+                texel[idx * 4 + 1],
+                texel[idx * 4 + 2],
+                texel[idx * 4 + 3],
+            ])
+        };
+
+        match self {
+            Self::R8Unorm => vec4(unorm8(0), 0., 0., 1.),
+            Self::Rg8Unorm => vec4(unorm8(0), unorm8(1), 0., 1.),
+            Self::Rgba8Unorm => vec4(unorm8(0), unorm8(1), unorm8(2), unorm8(3)),
+            Self::Rgba8UnormSrgb => vec4(
+                srgb_to_linear(unorm8(0)),
+                srgb_to_linear(unorm8(1)),
+                srgb_to_linear(unorm8(2)),
+                unorm8(3),
+            ),
+            Self::R16Float => vec4(float16(0), 0., 0., 1.),
+            Self::Rgba16Float => vec4(float16(0), float16(1), float16(2), float16(3)),
+            Self::R32Float | Self::Depth32Float => vec4(float32(0), 0., 0., 1.),
+            Self::Rgba32Float => vec4(float32(0), float32(1), float32(2), float32(3)),
+            Self::Depth16Unorm => vec4(
+                u16::from_le_bytes([texel[0], texel[1]]) as f32 / u16::MAX as f32,
+                0.,
+                0.,
+                1.,
+            ),
+        }
+    }
+
+    pub(crate) fn encode(self, color: Vec4, texel: &mut [u8]) {
+        let color: [f32; 4] = color.into();
+        let channels = match self {
+            Self::R8Unorm
+            | Self::R16Float
+            | Self::R32Float
+            | Self::Depth16Unorm
+            | Self::Depth32Float => 1,
+            Self::Rg8Unorm => 2,
+            _ => 4,
+        };
+
+        for (idx, value) in color.iter().take(channels).enumerate() {
+            match self {
+                Self::R8Unorm | Self::Rg8Unorm | Self::Rgba8Unorm => texel[idx] = to_unorm8(*value),
+                Self::Rgba8UnormSrgb => {
+                    texel[idx] = if idx < 3 {
+                        to_unorm8(linear_to_srgb(*value))
+                    } else {
+                        to_unorm8(*value)
+                    }
+                }
+                Self::R16Float | Self::Rgba16Float => texel[idx * 2..idx * 2 + 2]
+                    .copy_from_slice(&f16::from_f32(*value).to_le_bytes()),
+                Self::R32Float | Self::Rgba32Float | Self::Depth32Float => {
+                    texel[idx * 4..idx * 4 + 4].copy_from_slice(&value.to_le_bytes())
+                }
+                Self::Depth16Unorm => texel[..2].copy_from_slice(
+                    &((value.clamp(0., 1.) * u16::MAX as f32 + 0.5) as u16).to_le_bytes(),
+                ),
+            }
+        }
+    }
+}
+
+pub(crate) fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(crate) fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
+}
+
+fn to_unorm8(value: f32) -> u8 {
+    (value.clamp(0., 1.) * 255. + 0.5) as u8
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureDimension {
+    D2,
+    /// 2D layers of the same size, addressed by index.
+    D2Array,
+    /// Six square faces in the order +X, -X, +Y, -Y, +Z, -Z,
+    /// oriented like OpenGL cubemaps.
+    Cube,
+    /// A volume of texels, which unlike layers gets halved in depth by each mip level.
+    D3,
+}
 
 pub struct Texture {
+    pub(crate) format: TextureFormat,
+    pub(crate) dimension: TextureDimension,
+    //Level 0 is the full size image, each following level halves it
+    pub(crate) levels: Vec<MipLevel>,
+}
+
+//Holds every layer or depth slice of the texture, one after the other
+pub(crate) struct MipLevel {
     pub(crate) width: u32,
     pub(crate) height: u32,
+    pub(crate) depth: u32,
     pub(crate) data: Vec<u8>,
 }
+
+impl Texture {
+    /// Takes tightly packed texels in the given format, with multi-byte
+    /// values in little endian, and builds the full mip chain from them.
+    pub fn new(width: u32, height: u32, format: TextureFormat, data: Vec<u8>) -> Self {
+        Self::with_dimension(width, height, 1, TextureDimension::D2, format, data)
+    }
+
+    /// Takes the layers packed one after the other.
+    pub fn new_array(
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: TextureFormat,
+        data: Vec<u8>,
+    ) -> Self {
+        Self::with_dimension(
+            width,
+            height,
+            layers,
+            TextureDimension::D2Array,
+            format,
+            data,
+        )
+    }
+
+    /// Takes the six faces packed one after the other, in the order +X, -X, +Y, -Y, +Z, -Z.
+    pub fn new_cube(size: u32, format: TextureFormat, data: Vec<u8>) -> Self {
+        Self::with_dimension(size, size, 6, TextureDimension::Cube, format, data)
+    }
+
+    /// Takes the depth slices packed one after the other.
+    pub fn new_3d(
+        width: u32,
+        height: u32,
+        depth: u32,
+        format: TextureFormat,
+        data: Vec<u8>,
+    ) -> Self {
+        Self::with_dimension(width, height, depth, TextureDimension::D3, format, data)
+    }
+
+    fn with_dimension(
+        width: u32,
+        height: u32,
+        depth: u32,
+        dimension: TextureDimension,
+        format: TextureFormat,
+        data: Vec<u8>,
+    ) -> Self {
+        assert!(
+            width > 0 && height > 0,
+            "Textures need a width and height of at least one texel!"
+        );
+        assert!(
+            depth > 0,
+            "Textures need at least one layer or depth slice!"
+        );
+        assert_eq!(
+            data.len(),
+            Self::data_size(width, height, depth, format).expect("Texture is too large!"),
+            "Texture data does not match the given dimensions and format!"
+        );
+
+        let mut texture = Self {
+            format,
+            dimension,
+            levels: vec![MipLevel {
+                width,
+                height,
+                depth,
+                data,
+            }],
+        };
+        texture.generate_mips();
+        texture
+    }
+
+    //Bytes of data a texture of this size and format needs,
+    //or None if it's empty or too large to address
+    pub(crate) fn data_size(
+        width: u32,
+        height: u32,
+        depth: u32,
+        format: TextureFormat,
+    ) -> Option<usize> {
+        (width as usize)
+            .checked_mul(height as usize)?
+            .checked_mul(depth as usize)?
+            .checked_mul(format.bytes_per_texel())
+            .filter(|&size| size > 0)
+    }
+
+    //Rebuilds every level below the first
+    pub(crate) fn generate_mips(&mut self) {
+        self.levels.truncate(1);
+        while let Some(next) = self
+            .levels
+            .last()
+            .unwrap()
+            .downsample(self.format, self.dimension)
+        {
+            self.levels.push(next);
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    pub fn dimension(&self) -> TextureDimension {
+        self.dimension
+    }
+
+    /// Number of array layers, 6 for cubemaps or 1 for anything else.
+    pub fn layer_count(&self) -> u32 {
+        match self.dimension {
+            TextureDimension::D3 => 1,
+            _ => self.levels[0].depth,
+        }
+    }
+
+    /// Depth of 3D textures, or 1 for anything else.
+    pub fn depth(&self) -> u32 {
+        match self.dimension {
+            TextureDimension::D3 => self.levels[0].depth,
+            _ => 1,
+        }
+    }
+
+    pub fn mip_level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Overwrites a rectangle of the first layer with tightly packed texels in the
+    /// texture's format, and rebuilds the texels of each mip level made from it.
+    pub fn update_region(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<(), FuwaError> {
+        let level = &mut self.levels[0];
+        if x.checked_add(width).is_none_or(|right| right > level.width)
+            || y.checked_add(height)
+                .is_none_or(|bottom| bottom > level.height)
+        {
+            return Err(FuwaError::OutOfBounds);
+        }
+
+        let size = self.format.bytes_per_texel();
+        let row_length = width as usize * size;
+        let expected = row_length * height as usize;
+        if data.len() != expected {
+            return Err(FuwaError::DataSizeMismatch {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        if row_length == 0 {
+            return Ok(());
+        }
+        for (row, texels) in data.chunks_exact(row_length).enumerate() {
+            let index = level.texel_index(x, y + row as u32, 0) * size;
+            level.data[index..index + row_length].copy_from_slice(texels);
+        }
+
+        self.update_mips(x, y, x + width - 1, y + height - 1);
+        Ok(())
+    }
+
+    //Rebuilds the texels of every level below the first which are made from
+    //the rectangle of the first layer, given by its inclusive corners
+    fn update_mips(&mut self, mut x0: u32, mut y0: u32, mut x1: u32, mut y1: u32) {
+        let volume = self.dimension == TextureDimension::D3;
+        let size = self.format.bytes_per_texel();
+        for index in 1..self.levels.len() {
+            let (above, below) = self.levels.split_at_mut(index);
+            let (source, level) = (&above[index - 1], &mut below[0]);
+
+            x0 /= 2;
+            y0 /= 2;
+            x1 = (x1 / 2).min(level.width - 1);
+            y1 = (y1 / 2).min(level.height - 1);
+            //Odd sizes leave the last row/column out of the next level
+            if x0 > x1 || y0 > y1 {
+                return;
+            }
+
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    let average = source.downsample_texel(x, y, 0, self.format, volume);
+                    let index = level.texel_index(x, y, 0) * size;
+                    self.format
+                        .encode(average, &mut level.data[index..index + size]);
+                }
+            }
+        }
+    }
+}
+
+impl MipLevel {
+    //z is the layer, or depth slice for 3D textures
+    pub(crate) fn get_texel(&self, x: u32, y: u32, z: u32, format: TextureFormat) -> Vec4 {
+        let size = format.bytes_per_texel();
+        let index = self.texel_index(x, y, z) * size;
+        format.decode(&self.data[index..index + size])
+    }
+
+    fn texel_index(&self, x: u32, y: u32, z: u32) -> usize {
+        let (width, height) = (self.width as usize, self.height as usize);
+        x as usize + (y as usize + z as usize * height) * width
+    }
+
+    //Box filters every block of texels into one,
+    //returning None once the level is already down to a single texel
+    fn downsample(&self, format: TextureFormat, dimension: TextureDimension) -> Option<Self> {
+        let volume = dimension == TextureDimension::D3;
+        if self.width == 1 && self.height == 1 && (!volume || self.depth == 1) {
+            return None;
+        }
+
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let depth = if volume {
+            (self.depth / 2).max(1)
+        } else {
+            self.depth
+        };
+        let size = format.bytes_per_texel();
+        let mut level = Self {
+            width,
+            height,
+            depth,
+            data: vec![0; width as usize * height as usize * depth as usize * size],
+        };
+        for z in 0..depth {
+            for y in 0..height {
+                for x in 0..width {
+                    let average = self.downsample_texel(x, y, z, format, volume);
+                    let index = level.texel_index(x, y, z) * size;
+                    format.encode(average, &mut level.data[index..index + size]);
+                }
+            }
+        }
+
+        Some(level)
+    }
+
+    //Average of the 2x2 block of texels, or 2x2x2 for 3D textures,
+    //which makes up the texel of the next level
+    fn downsample_texel(
+        &self,
+        x: u32,
+        y: u32,
+        z: u32,
+        format: TextureFormat,
+        volume: bool,
+    ) -> Vec4 {
+        //Layers are downsampled on their own
+        let (z0, z1) = if volume {
+            ((z * 2).min(self.depth - 1), (z * 2 + 1).min(self.depth - 1))
+        } else {
+            (z, z)
+        };
+        //Odd sizes reuse the last row/column
+        let y0 = (y * 2).min(self.height - 1);
+        let y1 = (y * 2 + 1).min(self.height - 1);
+        let x0 = (x * 2).min(self.width - 1);
+        let x1 = (x * 2 + 1).min(self.width - 1);
+
+        (self.get_texel(x0, y0, z0, format)
+            + self.get_texel(x1, y0, z0, format)
+            + self.get_texel(x0, y1, z0, format)
+            + self.get_texel(x1, y1, z0, format)
+            + self.get_texel(x0, y0, z1, format)
+            + self.get_texel(x1, y0, z1, format)
+            + self.get_texel(x0, y1, z1, format)
+            + self.get_texel(x1, y1, z1, format))
+            * 0.125
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [TextureFormat; 10] = [
+        TextureFormat::R8Unorm,
+        TextureFormat::Rg8Unorm,
+        TextureFormat::Rgba8Unorm,
+        TextureFormat::Rgba8UnormSrgb,
+        TextureFormat::R16Float,
+        TextureFormat::Rgba16Float,
+        TextureFormat::R32Float,
+        TextureFormat::Rgba32Float,
+        TextureFormat::Depth16Unorm,
+        TextureFormat::Depth32Float,
+    ];
+
+    fn round_trip(format: TextureFormat, color: Vec4) -> Vec4 {
+        let mut texel = vec![0; format.bytes_per_texel()];
+        format.encode(color, &mut texel);
+        format.decode(&texel)
+    }
+
+    #[test]
+    fn encoding_round_trips_within_precision() {
+        let color = vec4(0.25, 0.5, 0.75, 1.);
+        for &format in FORMATS.iter() {
+            let decoded = round_trip(format, color);
+            let channels = match format {
+                TextureFormat::Rg8Unorm => 2,
+                TextureFormat::Rgba8Unorm
+                | TextureFormat::Rgba8UnormSrgb
+                | TextureFormat::Rgba16Float
+                | TextureFormat::Rgba32Float => 4,
+                _ => 1,
+            };
+            let decoded: [f32; 4] = decoded.into();
+            let color: [f32; 4] = color.into();
+            for channel in 0..channels {
+                assert!(
+                    (decoded[channel] - color[channel]).abs() < 0.01,
+                    "{:?} channel {}: {} != {}",
+                    format,
+                    channel,
+                    decoded[channel],
+                    color[channel]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn missing_channels_decode_as_zero_zero_zero_one() {
+        let color = vec4(0.5, 0.5, 0.5, 0.5);
+        assert_eq!(
+            round_trip(TextureFormat::R32Float, color),
+            vec4(0.5, 0., 0., 1.)
+        );
+        let rg = round_trip(TextureFormat::Rg8Unorm, color);
+        assert_eq!((rg.z(), rg.w()), (0., 1.));
+    }
+
+    #[test]
+    fn unorm_formats_clamp() {
+        assert_eq!(
+            round_trip(TextureFormat::Rgba8Unorm, vec4(-1., 2., 0., 1.)),
+            vec4(0., 1., 0., 1.)
+        );
+        assert_eq!(
+            round_trip(TextureFormat::Depth16Unorm, vec4(2., 0., 0., 0.)),
+            vec4(1., 0., 0., 1.)
+        );
+    }
+
+    #[test]
+    fn float_formats_keep_values_outside_of_zero_to_one() {
+        let color = vec4(-2., 16., 0.5, 3.);
+        assert_eq!(round_trip(TextureFormat::Rgba32Float, color), color);
+        assert_eq!(round_trip(TextureFormat::Rgba16Float, color), color);
+    }
+
+    #[test]
+    fn srgb_conversions_are_inverse() {
+        for step in 0..=255 {
+            let value = step as f32 / 255.;
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-5);
+        }
+        //Middle grey in sRGB is about a fifth as bright in linear
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 0.001);
+        assert_eq!(srgb_to_linear(0.), 0.);
+        assert!((srgb_to_linear(1.) - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn srgb_format_only_converts_color_channels() {
+        let grey = srgb_to_linear(128. / 255.);
+        let mut texel = [0; 4];
+        TextureFormat::Rgba8UnormSrgb.encode(vec4(grey, grey, grey, 0.5), &mut texel);
+        assert_eq!(texel, [128, 128, 128, 128]);
+
+        let decoded = TextureFormat::Rgba8UnormSrgb.decode(&[128, 0, 255, 128]);
+        assert!((decoded.x() - 0.2158).abs() < 0.001);
+        assert_eq!((decoded.y(), decoded.z()), (0., 1.));
+        assert!((decoded.w() - 128. / 255.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn updating_a_region_matches_rebuilding_every_mip() {
+        let format = TextureFormat::R32Float;
+        let texels = |seed: u32, count: u32| -> Vec<u8> {
+            (0..count)
+                .flat_map(|index| ((index * 7 + seed) as f32).to_le_bytes().to_vec())
+                .collect()
+        };
+
+        //Odd sizes, so some texels of each level don't make it into the next
+        let mut texture = Texture::new(7, 5, format, texels(0, 35));
+        for (seed, &(x, y, width, height)) in
+            [(6, 2, 1, 3), (1, 0, 2, 2), (3, 4, 4, 1), (0, 0, 7, 5)]
+                .iter()
+                .enumerate()
+        {
+            texture
+                .update_region(
+                    x,
+                    y,
+                    width,
+                    height,
+                    &texels(seed as u32 + 1, width * height),
+                )
+                .unwrap();
+            let rebuilt = Texture::new(7, 5, format, texture.levels[0].data.clone());
+            for (level, expected) in texture.levels.iter().zip(&rebuilt.levels) {
+                assert_eq!(level.data, expected.data);
+            }
+        }
+    }
+}
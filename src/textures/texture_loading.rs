@@ -0,0 +1,530 @@
+use crate::{
+    Fuwa, FuwaError, Handle, RenderTarget, Sampler, Texture, TextureFormat, TextureHandle,
+};
+use glam::*;
+use image::codecs::hdr::HdrDecoder;
+use image::{GenericImageView, ImageFormat, Rgb, Rgba};
+use std::path::Path;
+
+impl<T: RenderTarget> Fuwa<T> {
+    pub fn add_texture(&mut self, texture: Texture) -> TextureHandle {
+        self.uniforms.add_texture(texture)
+    }
+
+    pub fn add_sampler(&mut self, sampler: Sampler) -> Handle<Sampler> {
+        self.uniforms.add_sampler(sampler)
+    }
+
+    /// Loads an image from anywhere on disk as Rgba8Unorm or Rgba8UnormSrgb, or as
+    /// Rgba16Float or Rgba32Float to keep the precision of 16 bit and HDR images.
+    pub fn load_texture_from_path(
+        &mut self,
+        path: impl AsRef<Path>,
+        format: TextureFormat,
+    ) -> Result<TextureHandle, FuwaError> {
+        let bytes = std::fs::read(path)?;
+        self.load_texture_from_bytes(&bytes, format)
+    }
+
+    /// Decodes an image file already in memory, like one embedded with include_bytes.
+    pub fn load_texture_from_bytes(
+        &mut self,
+        bytes: &[u8],
+        format: TextureFormat,
+    ) -> Result<TextureHandle, FuwaError> {
+        check_image_format(format)?;
+        let (width, height, data) = decode_image(bytes, format)?;
+        Texture::data_size(width, height, 1, format).ok_or(FuwaError::InvalidSize)?;
+        Ok(self.add_texture(Texture::new(width, height, format, data)))
+    }
+
+    /// Creates a texture from tightly packed RGBA8 pixels, row by row.
+    /// Fails if either dimension is zero.
+    pub fn create_texture_from_rgba(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        data: Vec<u8>,
+    ) -> Result<TextureHandle, FuwaError> {
+        if !matches!(
+            format,
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+        ) {
+            return Err(FuwaError::UnsupportedFormat(format));
+        }
+        let expected =
+            Texture::data_size(width, height, 1, format).ok_or(FuwaError::InvalidSize)?;
+        if data.len() != expected {
+            return Err(FuwaError::DataSizeMismatch {
+                expected,
+                actual: data.len(),
+            });
+        }
+        Ok(self.add_texture(Texture::new(width, height, format, data)))
+    }
+
+    /// Overwrites a rectangle of the texture's first layer with tightly packed
+    /// texels in its format, like the next frame of an animated texture.
+    pub fn update_texture_region(
+        &mut self,
+        handle: TextureHandle,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<(), FuwaError> {
+        //The bound render texture's color data lives outside of its textures
+        if let Some(bound) = self.bound_render_texture {
+            if self.render_textures[bound].colors.contains(&handle) {
+                return Err(FuwaError::TextureInUse);
+            }
+        }
+
+        self.uniforms
+            .get_texture_mut(handle)
+            .ok_or(FuwaError::InvalidHandle)?
+            .update_region(x, y, width, height, data)
+    }
+
+    /// Removes the texture from the Uniforms, after which its handle may get reused.
+    /// Attachments of render textures can't be removed.
+    pub fn remove_texture(&mut self, handle: TextureHandle) -> Result<Texture, FuwaError> {
+        if self
+            .render_textures
+            .iter()
+            .any(|(_, render_texture)| render_texture.uses_texture(handle))
+        {
+            return Err(FuwaError::TextureInUse);
+        }
+
+        self.uniforms
+            .remove_texture(handle)
+            .ok_or(FuwaError::InvalidHandle)
+    }
+
+    /// Loads an image as Rgba8Unorm, without any sRGB decoding. Most color images
+    /// are sRGB encoded, so they should use load_texture_from_path with Rgba8UnormSrgb.
+    pub fn load_texture(&mut self, path: impl AsRef<Path>) -> Result<TextureHandle, FuwaError> {
+        self.load_texture_from_path(path, TextureFormat::Rgba8Unorm)
+    }
+
+    /// Loads every image as one layer of a texture array. They must all be the same size.
+    pub fn load_texture_array(
+        &mut self,
+        paths: &[impl AsRef<Path>],
+        format: TextureFormat,
+    ) -> Result<TextureHandle, FuwaError> {
+        check_image_format(format)?;
+        let images = paths
+            .iter()
+            .map(|path| read_image(path, format))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.add_texture_array(&images, format)
+    }
+
+    /// Like load_texture_array, with every layer's image file already in memory.
+    pub fn load_texture_array_from_bytes(
+        &mut self,
+        layers: &[&[u8]],
+        format: TextureFormat,
+    ) -> Result<TextureHandle, FuwaError> {
+        check_image_format(format)?;
+        let images = layers
+            .iter()
+            .map(|bytes| decode_image(bytes, format))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.add_texture_array(&images, format)
+    }
+
+    /// Loads a cubemap from six square images, in the order +X, -X, +Y, -Y, +Z, -Z.
+    pub fn load_cubemap(
+        &mut self,
+        paths: [impl AsRef<Path>; 6],
+        format: TextureFormat,
+    ) -> Result<TextureHandle, FuwaError> {
+        check_image_format(format)?;
+        let faces = paths
+            .iter()
+            .map(|path| read_image(path, format))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.add_cubemap(&faces, format)
+    }
+
+    /// Like load_cubemap, with the six image files already in memory.
+    pub fn load_cubemap_from_bytes(
+        &mut self,
+        faces: [&[u8]; 6],
+        format: TextureFormat,
+    ) -> Result<TextureHandle, FuwaError> {
+        check_image_format(format)?;
+        let faces = faces
+            .iter()
+            .map(|bytes| decode_image(bytes, format))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.add_cubemap(&faces, format)
+    }
+
+    /// Loads a cubemap from a single image of the faces unfolded into a cross,
+    /// either horizontal (4x3 faces) or vertical (3x4 faces, with -Z upside down at the bottom).
+    pub fn load_cubemap_cross(
+        &mut self,
+        path: impl AsRef<Path>,
+        format: TextureFormat,
+    ) -> Result<TextureHandle, FuwaError> {
+        check_image_format(format)?;
+        let image = read_image(path, format)?;
+        self.add_cubemap_cross(&image, format)
+    }
+
+    /// Like load_cubemap_cross, with the image file already in memory.
+    pub fn load_cubemap_cross_from_bytes(
+        &mut self,
+        bytes: &[u8],
+        format: TextureFormat,
+    ) -> Result<TextureHandle, FuwaError> {
+        check_image_format(format)?;
+        let image = decode_image(bytes, format)?;
+        self.add_cubemap_cross(&image, format)
+    }
+
+    fn add_texture_array(
+        &mut self,
+        images: &[Image],
+        format: TextureFormat,
+    ) -> Result<TextureHandle, FuwaError> {
+        let (width, height, _) = images.first().ok_or(FuwaError::InvalidSize)?;
+        let (width, height) = (*width, *height);
+        let layers = images.len() as u32;
+        let size =
+            Texture::data_size(width, height, layers, format).ok_or(FuwaError::InvalidSize)?;
+
+        let mut data = Vec::with_capacity(size);
+        for (image_width, image_height, image_data) in images {
+            if (*image_width, *image_height) != (width, height) {
+                return Err(FuwaError::ImageSizeMismatch);
+            }
+            data.extend_from_slice(image_data);
+        }
+
+        Ok(self.add_texture(Texture::new_array(width, height, layers, format, data)))
+    }
+
+    fn add_cubemap(
+        &mut self,
+        faces: &[Image],
+        format: TextureFormat,
+    ) -> Result<TextureHandle, FuwaError> {
+        let size = faces[0].0;
+        if faces
+            .iter()
+            .any(|(width, height, _)| *width != size || *height != size)
+        {
+            return Err(FuwaError::ImageSizeMismatch);
+        }
+        Texture::data_size(size, size, 6, format).ok_or(FuwaError::InvalidSize)?;
+
+        let data = faces.iter().flat_map(|(_, _, face)| face.iter().copied());
+        Ok(self.add_texture(Texture::new_cube(size, format, data.collect())))
+    }
+
+    fn add_cubemap_cross(
+        &mut self,
+        (width, height, image): &Image,
+        format: TextureFormat,
+    ) -> Result<TextureHandle, FuwaError> {
+        let (width, height) = (*width, *height);
+
+        //Column, row and whether it's upside down, for +X, -X, +Y, -Y, +Z, -Z
+        let (size, layout) = if width * 3 == height * 4 {
+            (
+                width / 4,
+                [
+                    (2, 1, false),
+                    (0, 1, false),
+                    (1, 0, false),
+                    (1, 2, false),
+                    (1, 1, false),
+                    (3, 1, false),
+                ],
+            )
+        } else if width * 4 == height * 3 {
+            (
+                width / 3,
+                [
+                    (2, 1, false),
+                    (0, 1, false),
+                    (1, 0, false),
+                    (1, 2, false),
+                    (1, 1, false),
+                    (1, 3, true),
+                ],
+            )
+        } else {
+            return Err(FuwaError::ImageSizeMismatch);
+        };
+        let data_size = Texture::data_size(size, size, 6, format).ok_or(FuwaError::InvalidSize)?;
+
+        let mut data = Vec::with_capacity(data_size);
+        for (column, row, flipped) in layout.iter() {
+            for y in 0..size {
+                for x in 0..size {
+                    let (x, y) = if *flipped {
+                        (size - 1 - x, size - 1 - y)
+                    } else {
+                        (x, y)
+                    };
+                    let index = 4
+                        * ((column * size + x) as usize
+                            + (row * size + y) as usize * width as usize);
+                    data.extend_from_slice(&image[index..index + 4]);
+                }
+            }
+        }
+
+        Ok(self.add_texture(Texture::new_cube(size, format, data)))
+    }
+}
+
+fn check_image_format(format: TextureFormat) -> Result<(), FuwaError> {
+    match format {
+        TextureFormat::Rgba8Unorm
+        | TextureFormat::Rgba8UnormSrgb
+        | TextureFormat::Rgba16Float
+        | TextureFormat::Rgba32Float => Ok(()),
+        _ => Err(FuwaError::UnsupportedFormat(format)),
+    }
+}
+
+//Width, height and texels in the format they were decoded as
+type Image = (u32, u32, Vec<u8>);
+
+fn read_image(path: impl AsRef<Path>, format: TextureFormat) -> Result<Image, FuwaError> {
+    decode_image(&std::fs::read(path)?, format)
+}
+
+fn decode_image(bytes: &[u8], format: TextureFormat) -> Result<Image, FuwaError> {
+    if let TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb = format {
+        let image = image::load_from_memory(bytes)?;
+        let (width, height) = image.dimensions();
+        return Ok((width, height, image.into_rgba8().into_raw()));
+    }
+
+    let (width, height, texels) = decode_float_image(bytes)?;
+    let size = format.bytes_per_texel();
+    let mut data = vec![0; texels.len() * size];
+    for (texel, bytes) in texels.iter().zip(data.chunks_exact_mut(size)) {
+        format.encode(*texel, bytes);
+    }
+    Ok((width, height, data))
+}
+
+//HDR images keep their full range, anything else gets normalized from 16 bits per channel
+fn decode_float_image(bytes: &[u8]) -> Result<(u32, u32, Vec<Vec4>), FuwaError> {
+    if image::guess_format(bytes)? == ImageFormat::Hdr {
+        let decoder = HdrDecoder::new(bytes)?;
+        let metadata = decoder.metadata();
+        let texels = decoder
+            .read_image_hdr()?
+            .into_iter()
+            .map(|Rgb([r, g, b])| vec4(r, g, b, 1.))
+            .collect();
+        return Ok((metadata.width, metadata.height, texels));
+    }
+
+    let image = image::load_from_memory(bytes)?.into_rgba16();
+    let texels = image
+        .pixels()
+        .map(|Rgba([r, g, b, a])| vec4(*r as f32, *g as f32, *b as f32, *a as f32) / 65535.)
+        .collect();
+    Ok((image.width(), image.height(), texels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TextureDimension;
+    use image::codecs::png::PngEncoder;
+    use image::{ColorType, DynamicImage, ImageOutputFormat, RgbaImage};
+
+    fn png(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 4]) -> Vec<u8> {
+        let image = RgbaImage::from_fn(width, height, |x, y| image::Rgba(pixel(x, y)));
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut bytes, ImageOutputFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    //The texel of the face, as the coordinates of the cross it was taken from
+    fn face_texel(texture: &Texture, face: u32, x: u32, y: u32) -> [u8; 2] {
+        let size = texture.width();
+        let index = 4 * (x + (y + face * size) * size) as usize;
+        let texel = &texture.levels[0].data[index..index + 2];
+        [texel[0], texel[1]]
+    }
+
+    #[test]
+    fn cubemap_faces_must_be_the_same_square_size() {
+        let mut fuwa = Fuwa::new_offscreen(1, 1, 1);
+        let square = png(2, 2, |_, _| [0; 4]);
+        let wide = png(2, 1, |_, _| [0; 4]);
+        let small = png(1, 1, |_, _| [0; 4]);
+
+        let mut faces = [square.as_slice(); 6];
+        faces[3] = &wide;
+        let result = fuwa.load_cubemap_from_bytes(faces, TextureFormat::Rgba8Unorm);
+        assert!(matches!(result, Err(FuwaError::ImageSizeMismatch)));
+
+        faces[3] = &small;
+        let result = fuwa.load_cubemap_from_bytes(faces, TextureFormat::Rgba8Unorm);
+        assert!(matches!(result, Err(FuwaError::ImageSizeMismatch)));
+
+        let layers = [square.as_slice(), &wide];
+        let result = fuwa.load_texture_array_from_bytes(&layers, TextureFormat::Rgba8Unorm);
+        assert!(matches!(result, Err(FuwaError::ImageSizeMismatch)));
+        let result = fuwa.load_texture_array_from_bytes(&[], TextureFormat::Rgba8Unorm);
+        assert!(matches!(result, Err(FuwaError::InvalidSize)));
+    }
+
+    #[test]
+    fn cubemap_crosses_are_cut_into_faces() {
+        let mut fuwa = Fuwa::new_offscreen(1, 1, 1);
+        let cross = |width, height| png(width, height, |x, y| [x as u8, y as u8, 0, 255]);
+        let cells = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1)];
+
+        let horizontal = fuwa
+            .load_cubemap_cross_from_bytes(&cross(8, 6), TextureFormat::Rgba8Unorm)
+            .unwrap();
+        let horizontal = fuwa.remove_texture(horizontal).unwrap();
+        assert_eq!(horizontal.dimension(), TextureDimension::Cube);
+        assert_eq!(horizontal.width(), 2);
+        for (face, &(column, row)) in cells.iter().chain(&[(3, 1)]).enumerate() {
+            for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter().copied() {
+                let expected = [(column * 2 + x) as u8, (row * 2 + y) as u8];
+                assert_eq!(face_texel(&horizontal, face as u32, x, y), expected);
+            }
+        }
+
+        //-Z hangs upside down below +Z
+        let vertical = fuwa
+            .load_cubemap_cross_from_bytes(&cross(6, 8), TextureFormat::Rgba8Unorm)
+            .unwrap();
+        let vertical = fuwa.remove_texture(vertical).unwrap();
+        assert_eq!(vertical.width(), 2);
+        for (face, &(column, row)) in cells.iter().enumerate() {
+            let expected = [(column * 2 + 1) as u8, (row * 2) as u8];
+            assert_eq!(face_texel(&vertical, face as u32, 1, 0), expected);
+        }
+        assert_eq!(face_texel(&vertical, 5, 0, 0), [3, 7]);
+        assert_eq!(face_texel(&vertical, 5, 1, 0), [2, 7]);
+        assert_eq!(face_texel(&vertical, 5, 0, 1), [3, 6]);
+
+        let result = fuwa.load_cubemap_cross_from_bytes(&cross(8, 4), TextureFormat::Rgba8Unorm);
+        assert!(matches!(result, Err(FuwaError::ImageSizeMismatch)));
+    }
+
+    #[test]
+    fn unreadable_images_are_errors() {
+        let mut fuwa = Fuwa::new_offscreen(1, 1, 1);
+        let result = fuwa.load_texture("./resources/missing.png");
+        assert!(matches!(result, Err(FuwaError::Io(_))));
+        let result = fuwa.load_texture_from_bytes(b"not an image", TextureFormat::Rgba8Unorm);
+        assert!(matches!(result, Err(FuwaError::Image(_))));
+    }
+
+    #[test]
+    fn rgba_data_must_match_the_size() {
+        let mut fuwa = Fuwa::new_offscreen(1, 1, 1);
+        let result = fuwa.create_texture_from_rgba(2, 2, TextureFormat::Rgba8Unorm, vec![0; 12]);
+        assert!(matches!(
+            result,
+            Err(FuwaError::DataSizeMismatch {
+                expected: 16,
+                actual: 12
+            })
+        ));
+        let result = fuwa.create_texture_from_rgba(2, 2, TextureFormat::R32Float, vec![0; 16]);
+        assert!(matches!(
+            result,
+            Err(FuwaError::UnsupportedFormat(TextureFormat::R32Float))
+        ));
+    }
+
+    #[test]
+    fn updates_must_fit_inside_the_texture() {
+        let mut fuwa = Fuwa::new_offscreen(1, 1, 1);
+        let handle = fuwa
+            .create_texture_from_rgba(2, 2, TextureFormat::Rgba8Unorm, vec![0; 16])
+            .unwrap();
+
+        let result = fuwa.update_texture_region(handle, 1, 1, 2, 1, &[0; 8]);
+        assert!(matches!(result, Err(FuwaError::OutOfBounds)));
+        let result = fuwa.update_texture_region(handle, u32::MAX, 0, 2, 1, &[0; 8]);
+        assert!(matches!(result, Err(FuwaError::OutOfBounds)));
+        let result = fuwa.update_texture_region(handle, 0, 0, 2, 1, &[0; 4]);
+        assert!(matches!(result, Err(FuwaError::DataSizeMismatch { .. })));
+
+        fuwa.update_texture_region(handle, 1, 1, 1, 1, &[255; 4])
+            .unwrap();
+        let texture = fuwa.remove_texture(handle).unwrap();
+        assert_eq!(&texture.levels[0].data[12..], &[255; 4]);
+    }
+
+    #[test]
+    fn removed_handles_are_stale() {
+        let mut fuwa = Fuwa::new_offscreen(1, 1, 1);
+        let handle = fuwa
+            .create_texture_from_rgba(1, 1, TextureFormat::Rgba8Unorm, vec![0; 4])
+            .unwrap();
+        fuwa.remove_texture(handle).unwrap();
+
+        //Even once another texture takes its slot
+        fuwa.create_texture_from_rgba(1, 1, TextureFormat::Rgba8Unorm, vec![0; 4])
+            .unwrap();
+        assert!(matches!(
+            fuwa.remove_texture(handle),
+            Err(FuwaError::InvalidHandle)
+        ));
+        let result = fuwa.update_texture_region(handle, 0, 0, 1, 1, &[0; 4]);
+        assert!(matches!(result, Err(FuwaError::InvalidHandle)));
+    }
+
+    #[test]
+    fn hdr_images_keep_their_range_in_float_formats() {
+        let mut fuwa = Fuwa::new_offscreen(1, 1, 1);
+        for &format in [TextureFormat::Rgba32Float, TextureFormat::Rgba16Float].iter() {
+            let handle = fuwa
+                .load_texture_from_path("./tests/fixtures/bright.hdr", format)
+                .unwrap();
+            let texture = fuwa.remove_texture(handle).unwrap();
+            assert_eq!(texture.format(), format);
+            assert_eq!((texture.width(), texture.height()), (2, 1));
+            let level = &texture.levels[0];
+            assert_eq!(level.get_texel(0, 0, 0, format), vec4(4., 0.5, 0.25, 1.));
+            assert_eq!(level.get_texel(1, 0, 0, format), vec4(0., 1., 16., 1.));
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_images_keep_their_precision_in_float_formats() {
+        let mut fuwa = Fuwa::new_offscreen(1, 1, 1);
+        //PNG stores 16 bit channels big endian
+        let texel = [1u16, 32768, 65535, 65535];
+        let texel = texel.iter().flat_map(|c| c.to_be_bytes().to_vec());
+        let mut bytes = Vec::new();
+        PngEncoder::new(&mut bytes)
+            .encode(&texel.collect::<Vec<_>>(), 1, 1, ColorType::Rgba16)
+            .unwrap();
+
+        let format = TextureFormat::Rgba32Float;
+        let handle = fuwa.load_texture_from_bytes(&bytes, format).unwrap();
+        let texel = fuwa.remove_texture(handle).unwrap().levels[0].get_texel(0, 0, 0, format);
+        assert_eq!(texel, vec4(1., 32768., 65535., 65535.) / 65535.);
+
+        //Only RGBA8 when created from raw texels
+        let result = fuwa.create_texture_from_rgba(1, 1, format, vec![0; 16]);
+        assert!(matches!(result, Err(FuwaError::UnsupportedFormat(_))));
+    }
+}
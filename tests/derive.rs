@@ -0,0 +1,147 @@
+use fuwa::glam::*;
+use fuwa::wide::f32x8;
+use fuwa::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, FSInput, FSInputSimd)]
+struct Varyings {
+    uv: Vec2,
+    #[interpolate(noperspective)]
+    screen: f32,
+    #[interpolate(flat)]
+    color: Vec3,
+}
+
+#[derive(Clone, Copy, FragmentOutput)]
+struct GBuffer {
+    albedo: Vec4,
+    normal: Vec4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, FSInput)]
+struct Pair(f32, Vec4);
+
+fn varyings(value: f32) -> Varyings {
+    Varyings {
+        uv: vec2(value, value * 2.),
+        screen: value,
+        color: Vec3::splat(value),
+    }
+}
+
+#[test]
+fn arithmetic_is_field_by_field() {
+    let a = varyings(1.);
+    let b = varyings(2.);
+    assert_eq!(a + b, varyings(3.));
+    assert_eq!(b - a, varyings(1.));
+    assert_eq!(a * 4., varyings(4.));
+    assert_eq!(b / 2., varyings(1.));
+}
+
+#[test]
+fn tuple_structs_use_field_indices() {
+    let pair = Pair(1., Vec4::splat(2.));
+    assert_eq!(pair + pair, Pair(2., Vec4::splat(4.)));
+    assert_eq!(pair * 0.5, Pair(0.5, Vec4::one()));
+}
+
+#[test]
+fn scale_by_mode_picks_the_factor_per_field() {
+    let scaled = varyings(1.).scale_by_mode(2., 3.);
+    assert_eq!(scaled.uv, vec2(2., 4.));
+    //Linear and flat fields both take the screen space factor
+    assert_eq!(scaled.screen, 3.);
+    assert_eq!(scaled.color, Vec3::splat(3.));
+}
+
+#[test]
+fn copy_flat_only_copies_flat_fields() {
+    let mut interpolated = varyings(0.5);
+    interpolated.copy_flat(&varyings(7.));
+    assert_eq!(interpolated.uv, vec2(0.5, 1.));
+    assert_eq!(interpolated.screen, 0.5);
+    assert_eq!(interpolated.color, Vec3::splat(7.));
+}
+
+fn lanes_equal(lanes: f32x8, values: [f32; 8]) -> bool {
+    lanes.cmp_eq(f32x8::from(values)).move_mask() == 0xFF
+}
+
+#[test]
+fn lanes_keep_the_field_names() {
+    let values = [0., 1., 2., 3., 4., 5., 6., 7.];
+    let lanes = Varyings::to_lanes(&values.map(varyings));
+    assert!(lanes_equal(lanes.screen, values));
+    assert!(lanes_equal(lanes.uv[1], values.map(|value| value * 2.)));
+    assert!(lanes_equal(lanes.color[2], values));
+}
+
+#[test]
+fn fragment_outputs_are_colors_in_declaration_order() {
+    let output = GBuffer {
+        albedo: vec4(1., 0., 0., 1.),
+        normal: vec4(0., 0., 1., 0.),
+    };
+    assert_eq!(GBuffer::COUNT, 2);
+    assert_eq!(output.color(0), output.albedo);
+    assert_eq!(output.color(1), output.normal);
+}
+
+#[derive(Clone)]
+struct FlatColor;
+
+impl FragmentShader<Varyings> for FlatColor {
+    type Output = Vec4;
+
+    fn fragment_shader_fn(
+        &self,
+        input: Varyings,
+        _context: &FragmentContext<Varyings>,
+        _uniforms: &Uniforms,
+    ) -> Option<Vec4> {
+        Some(input.color.extend(1.))
+    }
+}
+
+struct PassThrough;
+
+impl VertexShader<[f32; 5], Varyings> for PassThrough {
+    fn vertex_shader_fn(&self, vertex: &[f32; 5], _uniforms: &Uniforms) -> (Vec4, Varyings) {
+        let color = match vertex[2] as u32 {
+            0 => vec3(1., 0., 0.),
+            1 => vec3(0., 1., 0.),
+            _ => vec3(0., 0., 1.),
+        };
+        let varyings = Varyings {
+            uv: vec2(vertex[0], vertex[1]),
+            screen: vertex[0],
+            color,
+        };
+        (vec4(vertex[0], vertex[1], 0.5, 1.), varyings)
+    }
+}
+
+#[test]
+fn flat_fields_take_the_first_vertex_across_the_triangle() {
+    let mut fuwa = Fuwa::new_offscreen(8, 8, 1);
+    fuwa.set_cull_mode(CullMode::None);
+    let shader = fuwa.add_fragment_shader(FlatColor);
+
+    //Covers the whole framebuffer, with the vertex index as its third value
+    let vertices = [
+        [-1., -1., 0., 0., 0.],
+        [3., -1., 1., 0., 0.],
+        [-1., 3., 2., 0., 0.],
+    ];
+    let indices = [0, 1, 2];
+    let list = IndexedVertexList {
+        raw_vertex_list: &vertices,
+        index_list: &indices,
+    };
+    pipeline::draw(&mut fuwa, &PassThrough, shader, &list);
+    fuwa.render(shader);
+
+    for pixel in fuwa.get_frame().chunks_exact(4) {
+        assert_eq!(pixel, &[255, 0, 0, 255]);
+    }
+}
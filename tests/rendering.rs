@@ -0,0 +1,596 @@
+use fuwa::glam::*;
+use fuwa::*;
+use rayon::ThreadPoolBuilder;
+
+/// Takes positions straight from the vertices, at w = 1, and passes the rest on.
+struct ClipSpace;
+
+impl VertexShader<[f32; 6], Vec3> for ClipSpace {
+    fn vertex_shader_fn(&self, vertex: &[f32; 6], _uniforms: &Uniforms) -> (Vec4, Vec3) {
+        (
+            vec4(vertex[0], vertex[1], vertex[2], 1.),
+            vec3(vertex[3], vertex[4], vertex[5]),
+        )
+    }
+}
+
+//A triangle covering the whole framebuffer at the depth, passing the varyings on
+fn full_screen(depth: f32, varyings: Vec3) -> [[f32; 6]; 3] {
+    let [x, y, z] = [varyings.x(), varyings.y(), varyings.z()];
+    [
+        [-1., -1., depth, x, y, z],
+        [3., -1., depth, x, y, z],
+        [-1., 3., depth, x, y, z],
+    ]
+}
+
+fn draw<S: FragmentShader<Vec3> + 'static>(
+    fuwa: &mut Fuwa<Vec<u8>>,
+    shader: Handle<S>,
+    vertices: &[[f32; 6]],
+) {
+    let indices = (0..vertices.len()).collect::<Vec<_>>();
+    let list = IndexedVertexList {
+        raw_vertex_list: vertices,
+        index_list: &indices,
+    };
+    pipeline::draw(fuwa, &ClipSpace, shader, &list);
+}
+
+fn assert_every_pixel(fuwa: &mut Fuwa<Vec<u8>>, expected: [u8; 4]) {
+    for (index, pixel) in fuwa.get_frame().chunks_exact(4).enumerate() {
+        assert_eq!(pixel, &expected, "pixel {}", index);
+    }
+}
+
+/// Discards every odd triangle, coloring the rest by their primitive id.
+#[derive(Clone)]
+struct DiscardOdd;
+
+impl FragmentShader<Vec3> for DiscardOdd {
+    type Output = Vec4;
+    const CAN_DISCARD: bool = true;
+
+    fn fragment_shader_fn(
+        &self,
+        _input: Vec3,
+        context: &FragmentContext<Vec3>,
+        _uniforms: &Uniforms,
+    ) -> Option<Vec4> {
+        let id = context.primitive_id;
+        if id % 2 == 1 {
+            None
+        } else {
+            Some(vec4(id as f32 / 255., 0., 0., 1.))
+        }
+    }
+}
+
+#[test]
+fn overlapping_discarding_triangles_keep_the_nearest_kept_one() {
+    //Enough workers for triangles to race each other if they were drawn in parallel
+    let pool = ThreadPoolBuilder::new().num_threads(16).build().unwrap();
+    pool.install(|| {
+        let mut fuwa = Fuwa::new_offscreen(64, 64, 16);
+        fuwa.set_cull_mode(CullMode::None);
+        let shader = fuwa.add_fragment_shader(DiscardOdd);
+
+        //Each triangle is nearer than the last, so the last kept one should win
+        let vertices = (0..64)
+            .flat_map(|id| full_screen(0.9 - id as f32 * 0.01, Vec3::zero()).to_vec())
+            .collect::<Vec<_>>();
+        draw(&mut fuwa, shader, &vertices);
+        assert_every_pixel(&mut fuwa, [62, 0, 0, 255]);
+
+        //Triangle 62 also has to be the one which wrote depth
+        let behind = full_screen(0.9 - 61.5 * 0.01, Vec3::zero());
+        draw(&mut fuwa, shader, &behind);
+        assert_every_pixel(&mut fuwa, [62, 0, 0, 255]);
+        let in_front = full_screen(0.9 - 62.5 * 0.01, Vec3::zero());
+        draw(&mut fuwa, shader, &in_front);
+        assert_every_pixel(&mut fuwa, [0, 0, 0, 255]);
+    });
+}
+
+/// Outputs the same color everywhere.
+#[derive(Clone)]
+struct Solid(Vec4);
+
+impl FragmentShader<Vec3> for Solid {
+    type Output = Vec4;
+
+    fn fragment_shader_fn(
+        &self,
+        _input: Vec3,
+        _context: &FragmentContext<Vec3>,
+        _uniforms: &Uniforms,
+    ) -> Option<Vec4> {
+        Some(self.0)
+    }
+}
+
+#[test]
+fn overlapping_triangles_blend_over_each_other() {
+    let mut fuwa = Fuwa::new_offscreen(64, 64, 1);
+    fuwa.clear_color(&[0, 0, 0, 255]);
+    fuwa.set_depth_state(DepthState {
+        write_enabled: false,
+        compare: CompareFunction::LessEqual,
+        ..DepthState::default()
+    });
+    fuwa.set_blend_state(Some(BlendState::alpha_blending()));
+    let shader = fuwa.add_fragment_shader(Solid(vec4(1., 0., 0., 0.5)));
+
+    let mut vertices = full_screen(0.5, Vec3::zero()).to_vec();
+    vertices.extend_from_slice(&full_screen(0.5, Vec3::zero()));
+    draw(&mut fuwa, shader, &vertices);
+    fuwa.render(shader);
+
+    //Half of the red goes over black, then half again over that,
+    //with the first result rounded to 8 bits in between
+    for pixel in fuwa.get_frame().chunks_exact(4) {
+        assert!((191..=192).contains(&pixel[0]), "{:?}", pixel);
+        assert_eq!(&pixel[1..], &[0, 0, 255]);
+    }
+}
+
+#[test]
+fn write_masks_of_separate_draws_all_apply() {
+    let mut fuwa = Fuwa::new_offscreen(64, 64, 1);
+    fuwa.clear_color(&[0, 0, 0, 255]);
+    fuwa.set_depth_state(DepthState {
+        test_enabled: false,
+        ..DepthState::default()
+    });
+    let shader = fuwa.add_fragment_shader(Solid(Vec4::one()));
+
+    for &(red, blue) in [(true, false), (false, true)].iter() {
+        fuwa.set_color_write_mask(ColorWriteMask {
+            red,
+            green: false,
+            blue,
+            alpha: false,
+        });
+        draw(&mut fuwa, shader, &full_screen(0.5, Vec3::zero()));
+    }
+    fuwa.render(shader);
+
+    assert_every_pixel(&mut fuwa, [255, 0, 255, 255]);
+}
+
+#[test]
+fn triangles_sharing_an_edge_draw_its_pixels_once() {
+    let mut fuwa = Fuwa::new_offscreen(8, 8, 1);
+    fuwa.set_cull_mode(CullMode::None);
+    fuwa.set_depth_state(DepthState {
+        test_enabled: false,
+        ..DepthState::default()
+    });
+    fuwa.set_blend_state(Some(BlendState {
+        color: BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        },
+        ..BlendState::replace()
+    }));
+    let shader = fuwa.add_fragment_shader(Solid(vec4(0.25, 0., 0., 1.)));
+
+    //Both diagonals of the framebuffer, through the middle of pixels, in both windings
+    let corners = [[-1., 1.], [1., 1.], [1., -1.], [-1., -1.]];
+    for &[a, b, c, d] in [[0, 1, 2, 3], [1, 2, 3, 0], [0, 3, 2, 1], [1, 0, 3, 2]].iter() {
+        fuwa.clear();
+        let vertex = |corner: usize| {
+            let [x, y] = corners[corner];
+            [x, y, 0.5, 0., 0., 0.]
+        };
+        let quad = [
+            vertex(a),
+            vertex(b),
+            vertex(c),
+            vertex(a),
+            vertex(c),
+            vertex(d),
+        ];
+        draw(&mut fuwa, shader, &quad);
+        assert_every_pixel(&mut fuwa, [64, 0, 0, 255]);
+    }
+}
+
+fn draw_red_triangle<T: RenderTarget>(fuwa: &mut Fuwa<T>) {
+    fuwa.set_cull_mode(CullMode::None);
+    let shader = fuwa.add_fragment_shader(ColorBlend::new());
+    //Covers the middle of the view, two units in front of the camera
+    let vertices = [
+        [-1., -1., 2., 1., 0., 0.],
+        [1., -1., 2., 1., 0., 0.],
+        [0., 1., 2., 1., 0., 0.],
+    ];
+    let list = IndexedVertexList {
+        raw_vertex_list: &vertices,
+        index_list: &[0, 1, 2],
+    };
+    pipeline::draw(fuwa, &BasicVertexShader::new(), shader, &list);
+    fuwa.render(shader);
+}
+
+#[test]
+fn offscreen_fuwa_renders_into_its_frame() {
+    let mut fuwa = Fuwa::new_offscreen(16, 16, 1);
+    draw_red_triangle(&mut fuwa);
+
+    let frame = fuwa.into_target();
+    let pixel = |x: usize, y: usize| &frame[(x + y * 16) * 4..][..4];
+    assert_eq!(pixel(8, 8), &[255, 0, 0, 255]);
+    assert_eq!(pixel(0, 0), &[0, 0, 0, 0]);
+    assert_eq!(pixel(15, 15), &[0, 0, 0, 0]);
+}
+
+#[test]
+fn borrowed_frames_get_rendered_into() {
+    let mut frame = vec![0; 16 * 16 * 4];
+    let mut fuwa = Fuwa::with_target(16, 16, 1, frame.as_mut_slice());
+    draw_red_triangle(&mut fuwa);
+    drop(fuwa);
+
+    assert_eq!(&frame[(8 + 8 * 16) * 4..][..4], &[255, 0, 0, 255]);
+    assert_eq!(&frame[..4], &[0, 0, 0, 0]);
+}
+
+#[derive(Clone)]
+struct Tint(Vec4);
+
+/// Outputs the color of the bound Tint block.
+#[derive(Clone)]
+struct Tinted;
+
+impl FragmentShader<Vec3> for Tinted {
+    type Output = Vec4;
+
+    fn fragment_shader_fn(
+        &self,
+        _input: Vec3,
+        _context: &FragmentContext<Vec3>,
+        uniforms: &Uniforms,
+    ) -> Option<Vec4> {
+        Some(uniforms.get_block::<Tint>().unwrap().0)
+    }
+}
+
+#[test]
+fn changing_a_block_leaves_earlier_draws_alone() {
+    let mut fuwa = Fuwa::new_offscreen(64, 64, 1);
+    fuwa.set_cull_mode(CullMode::None);
+    let shader = fuwa.add_fragment_shader(Tinted);
+    let tint = fuwa.add_uniform_block(Tint(vec4(1., 0., 0., 1.)));
+    fuwa.bind_uniform_block(tint);
+
+    draw(&mut fuwa, shader, &full_screen(0.5, Vec3::zero()));
+    fuwa.get_uniform_block_mut(tint).unwrap().0 = vec4(0., 0., 1., 1.);
+    //The left half, in front of the first draw
+    let left_half = [
+        [-1., -1., 0.25, 0., 0., 0.],
+        [0., -1., 0.25, 0., 0., 0.],
+        [0., 1., 0.25, 0., 0., 0.],
+        [-1., -1., 0.25, 0., 0., 0.],
+        [0., 1., 0.25, 0., 0., 0.],
+        [-1., 1., 0.25, 0., 0., 0.],
+    ];
+    draw(&mut fuwa, shader, &left_half);
+    fuwa.render(shader);
+
+    for (index, pixel) in fuwa.get_frame().chunks_exact(4).enumerate() {
+        let expected = if index % 64 < 32 {
+            [0, 0, 255, 255]
+        } else {
+            [255, 0, 0, 255]
+        };
+        assert_eq!(pixel, &expected, "pixel {}", index);
+    }
+    assert_eq!(
+        fuwa.get_uniform_block(tint).unwrap().0,
+        vec4(0., 0., 1., 1.)
+    );
+}
+
+/// Projects world space positions through the matrix, passing the rest on.
+struct Projected(Mat4);
+
+impl VertexShader<[f32; 6], Vec3> for Projected {
+    fn vertex_shader_fn(&self, vertex: &[f32; 6], _uniforms: &Uniforms) -> (Vec4, Vec3) {
+        (
+            self.0 * vec4(vertex[0], vertex[1], vertex[2], 1.),
+            vec3(vertex[3], vertex[4], vertex[5]),
+        )
+    }
+}
+
+/// Outputs the varyings as the color.
+#[derive(Clone)]
+struct Varyings;
+
+impl FragmentShader<Vec3> for Varyings {
+    type Output = Vec4;
+
+    fn fragment_shader_fn(
+        &self,
+        input: Vec3,
+        _context: &FragmentContext<Vec3>,
+        _uniforms: &Uniforms,
+    ) -> Option<Vec4> {
+        Some(input.extend(1.))
+    }
+}
+
+fn draw_projected<S: FragmentShader<Vec3> + 'static>(
+    fuwa: &mut Fuwa<Vec<u8>>,
+    projection: Mat4,
+    shader: Handle<S>,
+    vertices: &[[f32; 6]],
+) {
+    let indices = (0..vertices.len()).collect::<Vec<_>>();
+    let list = IndexedVertexList {
+        raw_vertex_list: vertices,
+        index_list: &indices,
+    };
+    pipeline::draw(fuwa, &Projected(projection), shader, &list);
+    fuwa.render(shader);
+}
+
+//Two triangles of a square from -1 to 1 on x and y at the depth
+fn square(z: f32) -> [[f32; 6]; 6] {
+    let corner = |x: f32, y: f32| [x, y, z, 1., 0., 0.];
+    [
+        corner(-1., -1.),
+        corner(1., -1.),
+        corner(1., 1.),
+        corner(-1., -1.),
+        corner(1., 1.),
+        corner(-1., 1.),
+    ]
+}
+
+#[test]
+fn projection_matrices_place_the_geometry() {
+    //A square two units away with a 90 degree field of view, or twice the
+    //size of a square orthographic view, covers the middle half of the frame
+    let projections = [
+        (
+            Mat4::perspective_lh(std::f32::consts::FRAC_PI_2, 1., 0.5, 10.),
+            2.,
+        ),
+        (Mat4::orthographic_lh(-2., 2., -2., 2., 0., 10.), 5.),
+    ];
+    for &(projection, z) in projections.iter() {
+        let mut fuwa = Fuwa::new_offscreen(64, 64, 1);
+        fuwa.set_cull_mode(CullMode::None);
+        let shader = fuwa.add_fragment_shader(Varyings);
+        draw_projected(&mut fuwa, projection, shader, &square(z));
+
+        for (index, pixel) in fuwa.get_frame().chunks_exact(4).enumerate() {
+            let (x, y) = (index % 64, index / 64);
+            let inside = (16..48).contains(&x) && (16..48).contains(&y);
+            let expected = if inside { [255, 0, 0, 255] } else { [0; 4] };
+            assert_eq!(pixel, &expected, "pixel {}, {}", x, y);
+        }
+    }
+}
+
+#[test]
+fn varyings_are_interpolated_with_perspective_correction() {
+    let mut fuwa = Fuwa::new_offscreen(64, 64, 1);
+    fuwa.set_cull_mode(CullMode::None);
+    let shader = fuwa.add_fragment_shader(Varyings);
+    let projection = Mat4::perspective_lh(std::f32::consts::FRAC_PI_2, 1., 0.5, 10.);
+
+    //A floor from 1 to 3 units away, wide enough to get clipped on both sides,
+    //with a quarter of its distance in the red channel
+    let corner = |x: f32, z: f32| [x, -1., z, z / 4., 0., 0.];
+    let floor = [
+        corner(-4., 1.),
+        corner(4., 1.),
+        corner(4., 3.),
+        corner(-4., 1.),
+        corner(4., 3.),
+        corner(-4., 3.),
+    ];
+    draw_projected(&mut fuwa, projection, shader, &floor);
+
+    let frame = fuwa.get_frame();
+    for y in 43..64 {
+        //The floor is at y = -1, so the distance is -1 / ndc y,
+        //at the top left corner of the pixel
+        let ndc_y = 1. - y as f32 / 32.;
+        let expected = -1. / ndc_y / 4. * 255.;
+        for &x in [1, 31, 62].iter() {
+            let red = frame[(x + y * 64) * 4] as f32;
+            assert!(
+                (red - expected).abs() <= 1.,
+                "{}, {}: {} != {}",
+                x,
+                y,
+                red,
+                expected
+            );
+        }
+    }
+    assert_eq!(&frame[(31 + 42 * 64) * 4..][..4], &[0; 4]);
+}
+
+//Checks that exactly the pixels inside of the rectangle got drawn
+fn assert_drawn_rect(
+    fuwa: &mut Fuwa<Vec<u8>>,
+    x: std::ops::Range<usize>,
+    y: std::ops::Range<usize>,
+) {
+    let width = fuwa.width as usize;
+    for (index, pixel) in fuwa.get_frame().chunks_exact(4).enumerate() {
+        let (pixel_x, pixel_y) = (index % width, index / width);
+        let expected = if x.contains(&pixel_x) && y.contains(&pixel_y) {
+            [255, 255, 255, 255]
+        } else {
+            [0; 4]
+        };
+        assert_eq!(pixel, &expected, "pixel {}, {}", pixel_x, pixel_y);
+    }
+}
+
+#[test]
+fn viewports_and_scissors_clip_what_gets_drawn() {
+    let mut fuwa = Fuwa::new_offscreen(64, 64, 1);
+    let shader = fuwa.add_fragment_shader(Solid(Vec4::one()));
+    let draw_full_screen = |fuwa: &mut Fuwa<Vec<u8>>| {
+        fuwa.clear_all();
+        draw(fuwa, shader, &full_screen(0.5, Vec3::zero()));
+        fuwa.render(shader);
+    };
+
+    //The triangle reaches far past the viewport, but stays inside of it
+    fuwa.set_viewport(Viewport::new(16., 8., 32., 24.));
+    draw_full_screen(&mut fuwa);
+    assert_drawn_rect(&mut fuwa, 16..48, 8..32);
+
+    //Partly outside of the framebuffer
+    fuwa.set_viewport(Viewport::new(48., -16., 32., 32.));
+    draw_full_screen(&mut fuwa);
+    assert_drawn_rect(&mut fuwa, 48..64, 0..16);
+
+    //Only what's in both the viewport and the scissor
+    fuwa.set_viewport(Viewport::new(16., 8., 32., 24.));
+    fuwa.set_scissor(Some(ScissorRect::new(0, 20, 24, 40)));
+    draw_full_screen(&mut fuwa);
+    assert_drawn_rect(&mut fuwa, 16..24, 20..32);
+
+    fuwa.set_viewport(Viewport::new(0., 0., 64., 64.));
+    draw_full_screen(&mut fuwa);
+    assert_drawn_rect(&mut fuwa, 0..24, 20..60);
+
+    fuwa.set_scissor(None);
+    draw_full_screen(&mut fuwa);
+    assert_drawn_rect(&mut fuwa, 0..64, 0..64);
+}
+
+#[test]
+fn viewport_depth_ranges_remap_depth() {
+    let mut fuwa = Fuwa::new_offscreen(8, 8, 1);
+    let red = fuwa.add_fragment_shader(Solid(vec4(1., 0., 0., 1.)));
+    let blue = fuwa.add_fragment_shader(Solid(vec4(0., 0., 1., 1.)));
+
+    //The nearest depth of the first draw ends up halfway back
+    fuwa.set_viewport(Viewport {
+        min_depth: 0.5,
+        ..Viewport::new(0., 0., 8., 8.)
+    });
+    draw(&mut fuwa, red, &full_screen(0., Vec3::zero()));
+    fuwa.render(red);
+    assert_every_pixel(&mut fuwa, [255, 0, 0, 255]);
+
+    fuwa.set_viewport(Viewport::new(0., 0., 8., 8.));
+    draw(&mut fuwa, blue, &full_screen(0.6, Vec3::zero()));
+    fuwa.render(blue);
+    assert_every_pixel(&mut fuwa, [255, 0, 0, 255]);
+    draw(&mut fuwa, blue, &full_screen(0.4, Vec3::zero()));
+    fuwa.render(blue);
+    assert_every_pixel(&mut fuwa, [0, 0, 255, 255]);
+}
+
+/// Outputs a color made from the built-in inputs.
+#[derive(Clone)]
+struct BuiltIns(fn(&FragmentContext<Vec3>) -> Vec4);
+
+impl FragmentShader<Vec3> for BuiltIns {
+    type Output = Vec4;
+
+    fn fragment_shader_fn(
+        &self,
+        _input: Vec3,
+        context: &FragmentContext<Vec3>,
+        _uniforms: &Uniforms,
+    ) -> Option<Vec4> {
+        Some((self.0)(context))
+    }
+}
+
+fn unorm8(value: f32) -> u8 {
+    (value * 255. + 0.5) as u8
+}
+
+#[test]
+fn frag_coord_holds_the_pixel_depth_and_one_over_w() {
+    let mut fuwa = Fuwa::new_offscreen(64, 64, 1);
+    let shader = fuwa.add_fragment_shader(BuiltIns(|context| {
+        let coord = context.frag_coord;
+        vec4(coord.x() / 64., coord.y() / 64., coord.z(), coord.w())
+    }));
+    //Scales every clip space position, so only w differs once divided
+    let projection = Mat4::identity() * 2.;
+    draw_projected(
+        &mut fuwa,
+        projection,
+        shader,
+        &full_screen(0.25, Vec3::zero()),
+    );
+
+    for (index, pixel) in fuwa.get_frame().chunks_exact(4).enumerate() {
+        let (x, y) = ((index % 64) as f32, (index / 64) as f32);
+        let expected = [unorm8(x / 64.), unorm8(y / 64.), unorm8(0.25), unorm8(0.5)];
+        assert_eq!(pixel, &expected, "pixel {}", index);
+    }
+}
+
+#[test]
+fn front_facing_follows_the_winding() {
+    let mut fuwa = Fuwa::new_offscreen(8, 8, 1);
+    fuwa.set_depth_state(DepthState {
+        test_enabled: false,
+        ..DepthState::default()
+    });
+    let shader = fuwa.add_fragment_shader(BuiltIns(|context| {
+        if context.front_facing {
+            vec4(1., 0., 0., 1.)
+        } else {
+            vec4(0., 0., 1., 1.)
+        }
+    }));
+    let [a, b, c] = full_screen(0.5, Vec3::zero());
+
+    //Whichever winding survives back face culling faces the camera
+    fuwa.set_cull_mode(CullMode::Back);
+    draw(&mut fuwa, shader, &[a, b, c, a, c, b]);
+    fuwa.render(shader);
+    assert_every_pixel(&mut fuwa, [255, 0, 0, 255]);
+
+    fuwa.set_cull_mode(CullMode::Front);
+    draw(&mut fuwa, shader, &[a, b, c, a, c, b]);
+    fuwa.render(shader);
+    assert_every_pixel(&mut fuwa, [0, 0, 255, 255]);
+
+    fuwa.set_cull_mode(CullMode::None);
+    draw(&mut fuwa, shader, &[a, b, c]);
+    fuwa.render(shader);
+    let first = fuwa.get_frame()[..4].to_vec();
+    draw(&mut fuwa, shader, &[a, c, b]);
+    fuwa.render(shader);
+    assert_ne!(&fuwa.get_frame()[..4], first.as_slice());
+}
+
+#[test]
+fn barycentrics_weigh_the_vertices_in_index_order() {
+    let mut fuwa = Fuwa::new_offscreen(64, 64, 1);
+    let shader = fuwa.add_fragment_shader(BuiltIns(|context| context.barycentrics.extend(1.)));
+    fuwa.set_cull_mode(CullMode::None);
+    draw(&mut fuwa, shader, &full_screen(0.5, Vec3::zero()));
+    fuwa.render(shader);
+
+    //The second vertex is 4 units to the right of the first, and the third 4 units up
+    for (index, pixel) in fuwa.get_frame().chunks_exact(4).enumerate() {
+        let (x, y) = ((index % 64) as f32, (index / 64) as f32);
+        let right = x / 32. / 4.;
+        let up = (2. - y / 32.) / 4.;
+        let expected = [1. - right - up, right, up];
+        for channel in 0..3 {
+            let difference = pixel[channel] as i32 - unorm8(expected[channel]) as i32;
+            assert!(difference.abs() <= 1, "pixel {}: {:?}", index, pixel);
+        }
+    }
+}